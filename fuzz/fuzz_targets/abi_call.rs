@@ -0,0 +1,133 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Calls each ABI function with arbitrary arguments, from a contract whose
+//! memory is filled with arbitrary bytes.
+//!
+//! The input is laid out as a selector byte, picking the ABI function and the
+//! entrypoint, followed by three little-endian 8 bytes arguments and the
+//! bytes of the contract state.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use std::convert::TryInto;
+
+use canonical_host::MemStore;
+use parity_wasm::elements::{
+    CodeSection, ExportEntry, ExportSection, External, Func, FuncBody,
+    FunctionSection, FunctionType, ImportEntry, ImportSection, Instruction,
+    Instructions, Internal, MemorySection, MemoryType, Module, Section, Type,
+    TypeSection, ValueType,
+};
+use rusk_vm::{Contract, GasMeter, NetworkState, VMError};
+
+use ValueType::{I32, I64};
+
+const GAS_LIMIT: u64 = 1_000_000;
+
+/// The functions provided to contracts, with their signatures
+const ABI: &[(&str, &[ValueType], Option<ValueType>)] = &[
+    ("sig", &[I32, I32], None),
+    ("debug", &[I32, I32], None),
+    ("get", &[I32], None),
+    ("put", &[I32, I32, I32], None),
+    ("query", &[I32, I32], None),
+    ("transact", &[I32, I32], None),
+    ("callee", &[I32], None),
+    ("gas", &[I32], None),
+    ("block_height", &[], Some(I64)),
+    ("query_gas", &[I32, I32, I64], Some(I32)),
+    ("transact_gas", &[I32, I32, I64], Some(I32)),
+    ("reentered", &[], Some(I32)),
+];
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 25 {
+        return;
+    }
+
+    let selector = data[0] as usize;
+    let (name, params, ret) = ABI[selector % ABI.len()];
+    let transact = selector / ABI.len() % 2 == 1;
+
+    let args: Vec<u64> = data[1..25]
+        .chunks(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+
+    let code = caller(name, params, ret, &args);
+
+    let store = MemStore::new();
+    let mut network = NetworkState::<MemStore>::default();
+
+    let contract = Contract::new(data[25..].to_vec(), code, &store).unwrap();
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(GAS_LIMIT);
+    let result = if transact {
+        network
+            .transact::<_, u64>(contract_id, 0u64, &mut gas)
+            .map(|_| ())
+    } else {
+        network.query::<_, u64>(contract_id, 0u64, &mut gas).map(|_| ())
+    };
+
+    // Any error is fine, as long as the host did not panic.
+    if let Err(VMError::HostPanic(message)) = result {
+        panic!("host panic: {}", message);
+    }
+});
+
+// Builds a contract whose `q` and `t` entrypoints call the ABI function
+// `name` with `args`.
+fn caller(
+    name: &str,
+    params: &[ValueType],
+    ret: Option<ValueType>,
+    args: &[u64],
+) -> Vec<u8> {
+    let mut body: Vec<_> = params
+        .iter()
+        .zip(args)
+        .map(|(param, arg)| match param {
+            I64 => Instruction::I64Const(*arg as i64),
+            _ => Instruction::I32Const(*arg as i32),
+        })
+        .collect();
+    body.push(Instruction::Call(0));
+    if ret.is_some() {
+        body.push(Instruction::Drop);
+    }
+    body.push(Instruction::End);
+
+    let module = Module::new(vec![
+        Section::Type(TypeSection::with_types(vec![
+            Type::Function(FunctionType::new(params.to_vec(), ret)),
+            Type::Function(FunctionType::new(vec![I32], None)),
+        ])),
+        Section::Import(ImportSection::with_entries(vec![ImportEntry::new(
+            "env".into(),
+            name.into(),
+            External::Function(0),
+        )])),
+        Section::Function(FunctionSection::with_entries(vec![Func::new(1)])),
+        Section::Memory(MemorySection::with_entries(vec![MemoryType::new(
+            1, None,
+        )])),
+        Section::Export(ExportSection::with_entries(vec![
+            ExportEntry::new("memory".into(), Internal::Memory(0)),
+            ExportEntry::new("q".into(), Internal::Function(1)),
+            ExportEntry::new("t".into(), Internal::Function(1)),
+        ])),
+        Section::Code(CodeSection::with_bodies(vec![FuncBody::new(
+            vec![],
+            Instructions::new(body),
+        )])),
+    ]);
+
+    parity_wasm::serialize(module).unwrap()
+}
@@ -0,0 +1,31 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Decodes arbitrary bytes as a `NetworkState`, and re-encodes it.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use canonical::{ByteSink, ByteSource, Canon};
+use canonical_host::MemStore;
+use rusk_vm::NetworkState;
+
+fuzz_target!(|bytes: &[u8]| {
+    let store = MemStore::new();
+
+    let mut source = ByteSource::new(bytes, &store);
+    let state = match NetworkState::<MemStore>::read(&mut source) {
+        Ok(state) => state,
+        Err(_) => return,
+    };
+
+    // the decoded schedules must be usable
+    let _ = state.schedule();
+
+    let mut encoded = vec![0; state.encoded_len()];
+    let mut sink = ByteSink::new(&mut encoded[..], &store);
+    state.write(&mut sink).unwrap();
+});
@@ -0,0 +1,45 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Deploys arbitrary bytes as a contract, then queries and transacts with it.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use canonical_host::MemStore;
+use rusk_vm::{Contract, GasMeter, NetworkState, VMError};
+
+const GAS_LIMIT: u64 = 1_000_000;
+
+fuzz_target!(|code: &[u8]| {
+    let store = MemStore::new();
+    let mut network = NetworkState::<MemStore>::default();
+
+    let contract = Contract::new(0u64, code.to_vec(), &store).unwrap();
+
+    let contract_id = match network.deploy(contract) {
+        Ok(contract_id) => contract_id,
+        Err(error) => return check(error),
+    };
+
+    let mut gas = GasMeter::with_limit(GAS_LIMIT);
+    if let Err(error) = network.query::<_, u64>(contract_id, 0u64, &mut gas) {
+        check(error);
+    }
+
+    let mut gas = GasMeter::with_limit(GAS_LIMIT);
+    if let Err(error) = network.transact::<_, u64>(contract_id, 0u64, &mut gas)
+    {
+        check(error);
+    }
+});
+
+// Any error is fine, as long as the host did not panic.
+fn check(error: VMError<MemStore>) {
+    if let VMError::HostPanic(message) = error {
+        panic!("host panic: {}", message);
+    }
+}
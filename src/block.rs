@@ -0,0 +1,163 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use canonical::{Canon, Store};
+use dusk_abi::{ReturnValue, Transaction};
+
+use crate::contract::ContractId;
+use crate::fee::{Fee, Receipt};
+use crate::state::NetworkState;
+use crate::VMError;
+
+/// A transaction to be applied in a [`Block`], paid for by `payer`
+#[derive(Debug, Clone)]
+pub struct BlockTransaction {
+    payer: ContractId,
+    target: ContractId,
+    transaction: Transaction,
+    fee: Fee,
+}
+
+impl BlockTransaction {
+    /// Creates a new transaction with the contract at `target`, paid for by
+    /// `payer` according to `fee`
+    pub fn new<A, S>(
+        payer: ContractId,
+        target: ContractId,
+        transaction: &A,
+        fee: Fee,
+        store: &S,
+    ) -> Result<Self, S::Error>
+    where
+        A: Canon<S>,
+        S: Store,
+    {
+        Ok(BlockTransaction {
+            payer,
+            target,
+            transaction: Transaction::from_canon(transaction, store)?,
+            fee,
+        })
+    }
+}
+
+/// A block being executed on top of a [`NetworkState`], started with
+/// [`NetworkState::begin_block`]
+///
+/// Transactions are applied in order to a fork of the state, which only
+/// replaces the state once the block is finalized. Dropping the block, or
+/// discarding it, leaves the state untouched.
+pub struct Block<'a, S>
+where
+    S: Store,
+{
+    state: &'a mut NetworkState<S>,
+    fork: NetworkState<S>,
+    receipts: Vec<Receipt<ReturnValue, S>>,
+}
+
+impl<'a, S> Block<'a, S>
+where
+    S: Store,
+{
+    pub(crate) fn new(
+        state: &'a mut NetworkState<S>,
+        fork: NetworkState<S>,
+    ) -> Self {
+        Block {
+            state,
+            fork,
+            receipts: vec![],
+        }
+    }
+
+    /// Returns the height of the block
+    pub fn block_height(&self) -> u64 {
+        self.fork.block_height()
+    }
+
+    /// Returns the state as left by the transactions applied so far
+    pub fn state(&self) -> &NetworkState<S> {
+        &self.fork
+    }
+
+    /// Applies `transaction` on top of the previous ones, returning its
+    /// receipt
+    ///
+    /// A failing transaction is still part of the block, its payer being
+    /// charged for the gas it spent, and the failure is reported in its
+    /// receipt. A transaction whose payer cannot cover the fee is rejected
+    /// with [`VMError::NotEnoughFunds`], and is not part of the block.
+    pub fn apply(
+        &mut self,
+        transaction: BlockTransaction,
+    ) -> Result<&Receipt<ReturnValue, S>, VMError<S>> {
+        let BlockTransaction {
+            payer,
+            target,
+            transaction,
+            fee,
+        } = transaction;
+
+        let receipt = self.fork.with_fee(payer, fee, |state, gas_meter| {
+            state.transact_raw(target, transaction, gas_meter, None, Ok)
+        })?;
+
+        self.receipts.push(receipt);
+        Ok(self.receipts.last().expect("receipt was just pushed"))
+    }
+
+    /// Replaces the state by the one the block left, at the height of the
+    /// block, returning the new root along with the receipts of the
+    /// transactions applied
+    pub fn finalize(self) -> Result<FinalizedBlock<S>, VMError<S>> {
+        let root = self.fork.root()?;
+        let block_height = self.fork.block_height();
+
+        *self.state = self.fork;
+
+        Ok(FinalizedBlock {
+            block_height,
+            root,
+            receipts: self.receipts,
+        })
+    }
+
+    /// Drops the block along with all the transactions applied, leaving the
+    /// state untouched
+    pub fn discard(self) {}
+}
+
+/// The outcome of a finalized [`Block`]
+pub struct FinalizedBlock<S>
+where
+    S: Store,
+{
+    block_height: u64,
+    root: S::Ident,
+    receipts: Vec<Receipt<ReturnValue, S>>,
+}
+
+impl<S> FinalizedBlock<S>
+where
+    S: Store,
+{
+    /// Returns the height of the block
+    pub fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    /// Returns the root of the state after the block
+    pub fn root(&self) -> &S::Ident {
+        &self.root
+    }
+
+    /// Returns the receipts of the transactions of the block, in the order
+    /// they were applied
+    pub fn receipts(&self) -> &[Receipt<ReturnValue, S>] {
+        &self.receipts
+    }
+}
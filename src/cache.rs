@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use canonical::Store;
+
+use crate::contract::ContractId;
+use crate::engine::{EngineKind, Module};
+
+/// Default number of modules kept in the module cache of a [`NetworkState`]
+///
+/// [`NetworkState`]: crate::NetworkState
+pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+// Contract ids are the hash of the contract bytecode, so they double as the
+// code hash. Modules are instrumented according to the schedule and compiled
+// by an engine, so they are cached per schedule and engine. Schedules are
+// told apart by their fingerprint rather than their version, which nothing
+// prevents from being reused for different parameters.
+type CacheKey = (ContractId, u64, EngineKind);
+
+/// Hit and miss counters of the module cache of a [`NetworkState`]
+///
+/// [`NetworkState`]: crate::NetworkState
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of lookups that found a cached module
+    pub hits: u64,
+    /// Number of lookups that had to parse and instrument the bytecode
+    pub misses: u64,
+    /// Number of modules evicted to respect the capacity
+    pub evictions: u64,
+}
+
+/// A least-recently-used cache of parsed and instrumented wasm modules, keyed
+/// by code hash and schedule fingerprint
+pub(crate) struct ModuleCache<S: Store> {
+    capacity: usize,
+    // modules with the tick of their last use
+    entries: HashMap<CacheKey, (Rc<dyn Module<S>>, u64)>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl<S: Store> Default for ModuleCache<S> {
+    fn default() -> Self {
+        ModuleCache::with_capacity(DEFAULT_CACHE_CAPACITY)
+    }
+}
+
+impl<S: Store> ModuleCache<S> {
+    /// Creates a new, empty, `ModuleCache` holding at most `capacity` modules
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        ModuleCache {
+            capacity,
+            entries: HashMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Changes the maximum number of modules kept, evicting the least
+    /// recently used ones if needed
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    /// Returns the number of modules cached
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the hit and miss counters of the cache
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Returns the cached module for `key`, or the one built by `build`,
+    /// caching it.
+    pub(crate) fn get_or_try_insert<F, E>(
+        &mut self,
+        key: CacheKey,
+        build: F,
+    ) -> Result<Rc<dyn Module<S>>, E>
+    where
+        F: FnOnce() -> Result<Rc<dyn Module<S>>, E>,
+    {
+        self.tick += 1;
+
+        if let Some((module, last_used)) = self.entries.get_mut(&key) {
+            self.stats.hits += 1;
+            *last_used = self.tick;
+            return Ok(module.clone());
+        }
+
+        self.stats.misses += 1;
+        let module = build()?;
+        self.insert(key, module.clone());
+        Ok(module)
+    }
+
+    /// Caches `module` under `key`, evicting the least recently used module
+    /// if the cache is full
+    pub(crate) fn insert(&mut self, key: CacheKey, module: Rc<dyn Module<S>>) {
+        self.tick += 1;
+        self.entries.insert(key, (module, self.tick));
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| *key)
+                .expect("cache is not empty");
+
+            self.entries.remove(&oldest);
+            self.stats.evictions += 1;
+        }
+    }
+}
@@ -4,16 +4,20 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use canonical::{ByteSink, ByteSource, Canon, Sink, Store};
+use std::convert::{TryFrom, TryInto};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use canonical::{ByteSource, Canon, Store};
 use dusk_abi::{ContractState, Query, ReturnValue, Transaction};
 
-use wasmi::{
-    Externals, ImportsBuilder, MemoryRef, ModuleImportResolver, RuntimeArgs,
-    RuntimeValue, Trap, TrapKind,
-};
+use wasmi::{ModuleImportResolver, RuntimeArgs, RuntimeValue};
 
 use crate::contract::ContractId;
-use crate::gas::GasMeter;
+use crate::engine::Instance;
+use crate::gas::{Gas, GasMeter};
+use crate::ops::{slice, slice_mut};
+use crate::profiler::GasProfiler;
 use crate::state::NetworkState;
 use crate::VMError;
 
@@ -24,54 +28,76 @@ pub trait Resolver<S: Store>:
 
 pub use crate::resolver::CompoundResolver as StandardABI;
 
+/// Size of a page of wasm memory
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// Length of the header of a call buffer, holding the lengths of the two
+/// values following it as little-endian `u32`s
+const HEADER_LEN: usize = 8;
+
 #[derive(Debug)]
 enum Argument {
     Query(Query),
     Transaction(Transaction),
 }
 
-pub struct StackFrame {
+pub struct StackFrame<S: Store> {
     callee: ContractId,
     argument: Argument,
     ret: ReturnValue,
-    memory: MemoryRef,
+    instance: Rc<dyn Instance<S>>,
+    function: Option<u32>,
 }
 
-impl std::fmt::Debug for StackFrame {
+impl<S: Store> std::fmt::Debug for StackFrame<S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "(arg: {:?} return: {:?})", self.argument, self.ret)
     }
 }
 
-impl StackFrame {
-    fn new_query(callee: ContractId, memory: MemoryRef, query: Query) -> Self {
+impl<S: Store> StackFrame<S> {
+    fn new_query(
+        callee: ContractId,
+        instance: Rc<dyn Instance<S>>,
+        query: Query,
+    ) -> Self {
         StackFrame {
             callee,
-            memory,
+            instance,
             argument: Argument::Query(query),
             ret: Default::default(),
+            function: None,
         }
     }
 
     fn new_transaction(
         callee: ContractId,
-        memory: MemoryRef,
+        instance: Rc<dyn Instance<S>>,
         transaction: Transaction,
     ) -> Self {
         StackFrame {
             callee,
-            memory,
+            instance,
             argument: Argument::Transaction(transaction),
             ret: Default::default(),
+            function: None,
         }
     }
 
+    fn stack_height(&self) -> u32 {
+        self.instance.stack_height()
+    }
+
+    fn memory_limit_exceeded(&self) -> bool {
+        self.instance.memory_limit_exceeded()
+    }
+
     fn memory<R, C: FnOnce(&[u8]) -> R>(&self, closure: C) -> R {
-        self.memory.with_direct_access(closure)
+        self.instance.memory(|memory| closure(memory))
     }
 
     fn memory_mut<R, C: FnOnce(&mut [u8]) -> R>(&self, closure: C) -> R {
-        self.memory.with_direct_access_mut(closure)
+        self.instance.memory(closure)
     }
 }
 
@@ -83,11 +109,42 @@ pub trait Invoke<S: Store>: Sized {
     ) -> Result<Option<RuntimeValue>, VMError<S>>;
 }
 
+// The network state a call context executes against, only borrowed mutably
+// by contexts that may transact.
+enum StateRef<'a, S: Store> {
+    Shared(&'a NetworkState<S>),
+    Exclusive(&'a mut NetworkState<S>),
+}
+
+impl<'a, S: Store> StateRef<'a, S> {
+    fn get_mut(&mut self) -> Result<&mut NetworkState<S>, VMError<S>> {
+        match self {
+            StateRef::Shared(_) => Err(VMError::MutationInQuery),
+            StateRef::Exclusive(state) => Ok(&mut **state),
+        }
+    }
+}
+
+impl<'a, S: Store> Deref for StateRef<'a, S> {
+    type Target = NetworkState<S>;
+
+    fn deref(&self) -> &NetworkState<S> {
+        match self {
+            StateRef::Shared(state) => state,
+            StateRef::Exclusive(state) => state,
+        }
+    }
+}
+
 pub struct CallContext<'a, S: Store> {
-    state: &'a mut NetworkState<S>,
-    stack: Vec<StackFrame>,
+    state: StateRef<'a, S>,
+    stack: Vec<StackFrame<S>>,
     store: S,
     gas_meter: &'a mut GasMeter,
+    // meters of the gas-limited calls in progress, innermost last
+    limited_meters: Vec<GasMeter>,
+    profiler: Option<&'a mut GasProfiler>,
+    host_error: Option<VMError<S>>,
 }
 
 impl<'a, S> CallContext<'a, S>
@@ -99,12 +156,176 @@ where
         gas_meter: &'a mut GasMeter,
         store: &S,
     ) -> Result<Self, VMError<S>> {
-        Ok(CallContext {
+        Ok(Self::with_state(
+            StateRef::Exclusive(state),
+            gas_meter,
+            store,
+        ))
+    }
+
+    /// Creates a context borrowing the `state` immutably, in which only
+    /// queries can be executed
+    ///
+    /// Contracts trying to transact fail with [`VMError::MutationInQuery`].
+    pub fn new_query(
+        state: &'a NetworkState<S>,
+        gas_meter: &'a mut GasMeter,
+        store: &S,
+    ) -> Result<Self, VMError<S>> {
+        Ok(Self::with_state(StateRef::Shared(state), gas_meter, store))
+    }
+
+    fn with_state(
+        state: StateRef<'a, S>,
+        gas_meter: &'a mut GasMeter,
+        store: &S,
+    ) -> Self {
+        CallContext {
             state,
             stack: vec![],
             gas_meter,
+            limited_meters: vec![],
             store: store.clone(),
-        })
+            profiler: None,
+            host_error: None,
+        }
+    }
+
+    /// Attributes all gas charged from now on to `profiler`
+    pub fn profile_with(&mut self, profiler: &'a mut GasProfiler) {
+        self.profiler = Some(profiler);
+    }
+
+    /// Sets the function the topmost frame is executing, identified by its
+    /// index in the contract module
+    pub fn enter_function(&mut self, function: Option<u32>) {
+        if let Some(frame) = self.stack.last_mut() {
+            frame.function = function;
+        }
+    }
+
+    /// Keeps the error raised by a failing host call, for the engine to
+    /// surface once the trap it raises instead is unwound
+    pub(crate) fn fail(&mut self, error: VMError<S>) {
+        self.host_error = Some(error);
+    }
+
+    /// Takes the error raised by the last failing host call, if any
+    pub(crate) fn take_host_error(&mut self) -> Option<VMError<S>> {
+        self.host_error.take()
+    }
+
+    /// Returns true if the topmost frame went over the maximum stack height
+    pub(crate) fn stack_overflowed(&self) -> bool {
+        self.stack_height() > self.state.schedule().max_stack_height
+    }
+
+    /// Returns true if the topmost frame trapped on growing its memory beyond
+    /// the maximum
+    pub(crate) fn memory_limit_exceeded(&self) -> bool {
+        self.stack
+            .last()
+            .map(StackFrame::memory_limit_exceeded)
+            .unwrap_or(false)
+    }
+
+    /// Returns true if the contract of the topmost frame has been re-entered,
+    /// having another frame on the stack
+    pub fn reentered(&self) -> bool {
+        match self.stack.split_last() {
+            Some((top, below)) => {
+                below.iter().any(|frame| frame.callee == top.callee)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns true if a query is in progress, the state being read-only
+    pub fn in_query(&self) -> bool {
+        self.stack
+            .iter()
+            .any(|frame| matches!(frame.argument, Argument::Query(_)))
+    }
+
+    // Fails if a new frame would exceed the maximum call depth.
+    fn check_call_depth(&self) -> Result<(), VMError<S>> {
+        let max_call_depth = self.state.schedule().max_call_depth as usize;
+
+        if self.stack.len() >= max_call_depth {
+            Err(VMError::CallDepthExceeded)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Fails if calling `target` would re-enter it in violation of the
+    // reentrancy policy of the state.
+    fn check_reentrancy(&self, target: &ContractId) -> Result<(), VMError<S>> {
+        if !self.stack.iter().any(|frame| frame.callee == *target) {
+            return Ok(());
+        }
+
+        let self_call = self.callee() == target;
+
+        if self.state.reentrancy_policy().permits(self_call) {
+            Ok(())
+        } else {
+            Err(VMError::Reentrancy)
+        }
+    }
+
+    /// Returns the stack height reached by the topmost frame
+    fn stack_height(&self) -> u32 {
+        self.stack.last().map(StackFrame::stack_height).unwrap_or(0)
+    }
+
+    // Creates an instance of the contract at `target`, whose stack height
+    // starts counting from the height reached by the calling frame, and
+    // writes its state and `argument` to a call buffer at the end of its
+    // memory, returning the instance with the offset of the buffer.
+    //
+    // The memory is grown to fit the buffer, charging for the pages added.
+    fn instantiate(
+        &mut self,
+        target: ContractId,
+        argument: &[u8],
+    ) -> Result<(Rc<dyn Instance<S>>, usize), VMError<S>> {
+        let (instance, state_len) = {
+            let contract = self.state.get_contract(&target)?;
+            let module = self.state.instrumented_module(&target)?;
+
+            let instance = module.instantiate()?;
+            instance.set_stack_height(self.stack_height())?;
+
+            if let Some(profiler) = &mut self.profiler {
+                profiler.load_names(target, contract.bytecode());
+            }
+
+            (instance, contract.state().as_bytes().len())
+        };
+
+        let len = HEADER_LEN + state_len + argument.len();
+        let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+
+        let grow_cost = self.state.schedule().grow_mem_cost;
+        self.charge(grow_cost.saturating_mul(pages as Gas))?;
+
+        let buffer = instance
+            .grow_memory(pages as u32)
+            .map_err(|_| VMError::CallBufferTooLarge(len))?
+            as usize
+            * PAGE_SIZE;
+
+        let contract = self.state.get_contract(&target)?;
+        instance.memory(|memory| {
+            write_arguments(
+                slice_mut(memory, buffer, len)?,
+                contract.state().as_bytes(),
+                argument,
+            )
+        })?;
+
+        Ok((instance, buffer))
     }
 
     pub fn query(
@@ -112,64 +333,46 @@ where
         target: ContractId,
         query: Query,
     ) -> Result<ReturnValue, VMError<S>> {
-        let resolver = StandardABI::<S>::default();
-        let imports = ImportsBuilder::new()
-            .with_resolver("env", &resolver)
-            .with_resolver("canon", &resolver);
+        // is this a reserved module call?
+        if self.state.modules().borrow().contains_key(&target) {
+            self.charge_call(query.as_bytes().len(), false)?;
 
-        let instance;
+            let result = self.state.modules().borrow()[&target]
+                .execute(query)
+                .map_err(VMError::from_store_error)?;
 
-        let store = self.store.clone();
+            self.charge_return(&result)?;
+            return Ok(result);
+        }
 
-        if let Some(module) = self.state.modules().borrow().get(&target) {
-            // is this a reserved module call?
-            return module.execute(query).map_err(VMError::from_store_error);
-        } else {
-            let contract = self.state.get_contract(&target)?;
+        self.check_call_depth()?;
+        self.check_reentrancy(&target)?;
+        let state_len = self.state_len(&target)?;
+        self.charge_call(state_len + query.as_bytes().len(), true)?;
 
-            let module = wasmi::Module::from_buffer(contract.bytecode())?;
-
-            instance = wasmi::ModuleInstance::new(&module, &imports)?
-                .assert_no_start();
-
-            match instance.export_by_name("memory") {
-                Some(wasmi::ExternVal::Memory(memref)) => {
-                    // write contract state and argument to memory
-                    memref
-                        .with_direct_access_mut(|m| {
-                            let mut sink = ByteSink::new(&mut m[..], &store);
-                            // copy the raw bytes only, since the
-                            // contract
-                            // can infer
-                            // it's own state and argument lengths
-                            sink.copy_bytes(contract.state().as_bytes());
-                            sink.copy_bytes(query.as_bytes());
-                            Ok(())
-                        })
-                        .map_err(VMError::from_store_error)?;
-
-                    self.stack
-                        .push(StackFrame::new_query(target, memref, query));
-                }
-                _ => return Err(VMError::MemoryNotFound),
-            }
-        }
+        let store = self.store.clone();
+
+        let (instance, buffer) = self.instantiate(target, query.as_bytes())?;
+        self.stack
+            .push(StackFrame::new_query(target, instance.clone(), query));
 
         // Perform the query call
-        instance.invoke_export("q", &[wasmi::RuntimeValue::I32(0)], self)?;
-
-        match instance.export_by_name("memory") {
-            Some(wasmi::ExternVal::Memory(memref)) => memref
-                .with_direct_access_mut(|m| {
-                    let mut source = ByteSource::new(&m[..], &store);
-                    let result = Canon::<S>::read(&mut source)?;
-
-                    self.stack.pop();
-                    Ok(result)
-                })
-                .map_err(VMError::from_store_error),
-            _ => Err(VMError::MemoryNotFound),
+        if let Err(error) = instance.invoke("q", buffer as u32, self) {
+            self.stack.pop();
+            return Err(error);
         }
+
+        self.stack.pop();
+
+        let result = instance.memory(|m| {
+            let (_, ret) = read_output(m, buffer)?;
+
+            let mut source = ByteSource::new(ret, &store);
+            Canon::<S>::read(&mut source).map_err(VMError::from_store_error)
+        })?;
+
+        self.charge_return(&result)?;
+        Ok(result)
     }
 
     pub fn transact(
@@ -177,68 +380,52 @@ where
         target: ContractId,
         transaction: Transaction,
     ) -> Result<(ContractState, ReturnValue), VMError<S>> {
-        let resolver = StandardABI::<S>::default();
-        let imports = ImportsBuilder::new()
-            .with_resolver("env", &resolver)
-            .with_resolver("canon", &resolver);
+        if self.in_query() || matches!(self.state, StateRef::Shared(_)) {
+            return Err(VMError::MutationInQuery);
+        }
 
-        let instance;
+        self.check_call_depth()?;
+        self.check_reentrancy(&target)?;
+        let state_len = self.state_len(&target)?;
+        self.charge_call(state_len + transaction.as_bytes().len(), true)?;
 
         let store = self.store.clone();
-        {
-            let contract = self.state.get_contract(&target)?;
-            let module = wasmi::Module::from_buffer(contract.bytecode())?;
-
-            instance = wasmi::ModuleInstance::new(&module, &imports)?
-                .assert_no_start();
-
-            match instance.export_by_name("memory") {
-                Some(wasmi::ExternVal::Memory(memref)) => {
-                    // write contract state and argument to memory
-
-                    memref.with_direct_access_mut(|m| {
-                        let mut sink = ByteSink::new(&mut m[..], &store);
-                        // copy the raw bytes only, since the contract can
-                        // infer it's own state and argument lengths.
-                        sink.copy_bytes(contract.state().as_bytes());
-                        sink.copy_bytes(transaction.as_bytes());
-                    });
-
-                    self.stack.push(StackFrame::new_transaction(
-                        target,
-                        memref,
-                        transaction,
-                    ));
-                }
-                _ => return Err(VMError::MemoryNotFound),
-            }
-        }
+
+        let (instance, buffer) =
+            self.instantiate(target, transaction.as_bytes())?;
+        self.stack.push(StackFrame::new_transaction(
+            target,
+            instance.clone(),
+            transaction,
+        ));
+
         // Perform the transact call
-        instance.invoke_export("t", &[wasmi::RuntimeValue::I32(0)], self)?;
+        if let Err(error) = instance.invoke("t", buffer as u32, self) {
+            self.stack.pop();
+            return Err(error);
+        }
 
-        let ret = {
-            let mut contract = self.state.get_contract_mut(&target)?;
+        let output: Result<_, VMError<S>> = instance.memory(|m| {
+            let (state, ret) = read_output(m, buffer)?;
 
-            match instance.export_by_name("memory") {
-                Some(wasmi::ExternVal::Memory(memref)) => {
-                    memref
-                        .with_direct_access_mut(|m| {
-                            let mut source = ByteSource::new(&m[..], &store);
+            // read new state
+            let state: ContractState =
+                Canon::<S>::read(&mut ByteSource::new(state, &store))
+                    .map_err(VMError::from_store_error)?;
 
-                            // read new state
-                            let state = Canon::<S>::read(&mut source)?;
+            // read return value
+            let ret: ReturnValue =
+                Canon::<S>::read(&mut ByteSource::new(ret, &store))
+                    .map_err(VMError::from_store_error)?;
 
-                            // update new self state
-                            *(*contract).state_mut() = state;
+            Ok((state, ret))
+        });
 
-                            // read return value
-                            Canon::<S>::read(&mut source)
-                        })
-                        .map_err(VMError::from_store_error)
-                }
-                _ => return Err(VMError::MemoryNotFound),
-            }
-        };
+        // update new self state
+        let ret = output.and_then(|(state, ret)| {
+            self.state.get_mut()?.set_contract_state(&target, state)?;
+            Ok(ret)
+        });
 
         let state = if self.stack.len() > 1 {
             self.stack.pop();
@@ -250,14 +437,140 @@ where
             state
         };
 
-        Ok((state, ret?))
+        let ret = ret?;
+        self.charge_return(&ret)?;
+
+        Ok((state, ret))
+    }
+
+    /// Charges `amount` of gas to the active meter
+    pub fn charge(&mut self, amount: Gas) -> Result<(), VMError<S>> {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(
+                self.stack
+                    .iter()
+                    .map(|frame| (frame.callee, frame.function)),
+                amount,
+            );
+        }
+
+        if self.gas_meter_mut().charge(amount).is_out_of_gas() {
+            return Err(VMError::OutOfGas);
+        }
+        Ok(())
+    }
+
+    /// Charges the base cost of a call, plus the cost of copying `len` bytes
+    /// into the memory of the callee, and the cost of instantiating the
+    /// callee if `instantiate` is set.
+    ///
+    /// Calls are charged before any work is done on behalf of the callee.
+    fn charge_call(
+        &mut self,
+        len: usize,
+        instantiate: bool,
+    ) -> Result<(), VMError<S>> {
+        let schedule = self.state.schedule();
+
+        let mut cost = schedule
+            .call_base_cost
+            .saturating_add(per_byte(schedule.sandbox_data_write_cost, len));
+
+        if instantiate {
+            cost = cost.saturating_add(schedule.instantiate_base_cost);
+        }
+
+        self.charge(cost)
     }
 
+    // Returns the length of the state of the contract at `target`, which is
+    // copied to its memory along with the argument of every call.
+    fn state_len(&self, target: &ContractId) -> Result<usize, VMError<S>> {
+        Ok(self.state.get_contract(target)?.state().as_bytes().len())
+    }
+
+    /// Charges the cost of copying the return value `ret` out of the memory of
+    /// the callee.
+    fn charge_return(&mut self, ret: &ReturnValue) -> Result<(), VMError<S>> {
+        let cost = per_byte(
+            self.state.schedule().return_data_per_byte_cost,
+            ret.as_bytes().len(),
+        );
+        self.charge(cost)
+    }
+
+    /// Queries the contract at `target`, with at most `gas_limit` gas
+    /// available to it
+    ///
+    /// Gas left unused by the callee is returned to the caller.
+    pub fn query_with_gas(
+        &mut self,
+        target: ContractId,
+        query: Query,
+        gas_limit: Gas,
+    ) -> Result<ReturnValue, VMError<S>> {
+        self.with_gas_limit(gas_limit, |context| context.query(target, query))
+    }
+
+    /// Transacts with the contract at `target`, with at most `gas_limit` gas
+    /// available to it
+    ///
+    /// Gas left unused by the callee is returned to the caller. If the
+    /// transaction fails, the changes it made to the state are reverted.
+    pub fn transact_with_gas(
+        &mut self,
+        target: ContractId,
+        transaction: Transaction,
+        gas_limit: Gas,
+    ) -> Result<(ContractState, ReturnValue), VMError<S>> {
+        let snapshot = self.state_mut()?.clone();
+
+        let result = self.with_gas_limit(gas_limit, |context| {
+            context.transact(target, transaction)
+        });
+
+        if result.is_err() {
+            *self.state_mut()? = snapshot;
+        }
+
+        result
+    }
+
+    // Runs `call` against a meter carved out of the active one, charging the
+    // active meter only with what was actually spent. The profiler already
+    // recorded that gas against the frames of the call, so it is not
+    // recorded again.
+    fn with_gas_limit<R, C>(
+        &mut self,
+        gas_limit: Gas,
+        call: C,
+    ) -> Result<R, VMError<S>>
+    where
+        C: FnOnce(&mut Self) -> Result<R, VMError<S>>,
+    {
+        let gas_limit = gas_limit.min(self.gas_meter_mut().gas_left());
+        self.limited_meters.push(GasMeter::with_limit(gas_limit));
+
+        let result = call(self);
+
+        let meter = self.limited_meters.pop().expect("Invalid meter stack");
+        if self.gas_meter_mut().charge(meter.spent()).is_out_of_gas() {
+            return Err(VMError::OutOfGas);
+        }
+
+        result
+    }
+
+    /// Returns the meter gas is charged to, which is the meter of the
+    /// innermost gas-limited call, if any
     pub fn gas_meter_mut(&mut self) -> &mut GasMeter {
-        self.gas_meter
+        match self.limited_meters.last_mut() {
+            Some(meter) => meter,
+            None => &mut *self.gas_meter,
+        }
     }
 
-    pub fn top(&self) -> &StackFrame {
+    pub fn top(&self) -> &StackFrame<S> {
         self.stack.last().expect("Invalid stack")
     }
 
@@ -273,10 +586,10 @@ where
         &self.store
     }
 
-    pub fn memory_mut<R, C: FnOnce(&mut [u8]) -> Result<R, S::Error>>(
+    pub fn memory_mut<R, C: FnOnce(&mut [u8]) -> R>(
         &mut self,
         closure: C,
-    ) -> Result<R, S::Error> {
+    ) -> R {
         self.stack
             .last_mut()
             .expect("Invalid stack")
@@ -287,34 +600,58 @@ where
         &self.state
     }
 
-    pub fn state_mut(&mut self) -> &mut NetworkState<S> {
-        &mut self.state
+    /// Returns the state mutably, failing with [`VMError::MutationInQuery`]
+    /// if the context only borrows it for queries
+    pub fn state_mut(&mut self) -> Result<&mut NetworkState<S>, VMError<S>> {
+        self.state.get_mut()
     }
 }
 
-/// Convenience function to construct host traps
-pub fn host_trap<S: Store>(host: VMError<S>) -> Trap {
-    Trap::new(TrapKind::Host(Box::new(host)))
+// Writes the state of a contract and its argument to its call `buffer`,
+// after a header holding their lengths.
+fn write_arguments<S: Store>(
+    buffer: &mut [u8],
+    state: &[u8],
+    argument: &[u8],
+) -> Result<(), VMError<S>> {
+    let (header, values) = buffer.split_at_mut(HEADER_LEN);
+    header[..4].copy_from_slice(&length(state.len())?);
+    header[4..].copy_from_slice(&length(argument.len())?);
+
+    let (state_bytes, argument_bytes) = values.split_at_mut(state.len());
+    state_bytes.copy_from_slice(state);
+    argument_bytes[..argument.len()].copy_from_slice(argument);
+    Ok(())
 }
 
-impl<'a, S> Externals for CallContext<'a, S>
-where
-    S: Store,
-{
-    fn invoke_index(
-        &mut self,
-        index: usize,
-        args: RuntimeArgs,
-    ) -> Result<Option<RuntimeValue>, Trap> {
-        match StandardABI::invoke(self, index, args) {
-            Ok(ok) => Ok(ok),
-            Err(e) => {
-                if let VMError::Trap(t) = e {
-                    Err(t)
-                } else {
-                    Err(host_trap(e))
-                }
-            }
-        }
-    }
+// Reads the new state and the return value a contract wrote to its call
+// buffer at offset `buffer`, after a header holding their lengths. Queries
+// write an empty state.
+fn read_output<S: Store>(
+    memory: &[u8],
+    buffer: usize,
+) -> Result<(&[u8], &[u8]), VMError<S>> {
+    let header = slice(memory, buffer, HEADER_LEN)?;
+    let state_len = u32::from_le_bytes(
+        header[..4].try_into().expect("header holds two lengths"),
+    ) as usize;
+    let ret_len = u32::from_le_bytes(
+        header[4..].try_into().expect("header holds two lengths"),
+    ) as usize;
+
+    let state = slice(memory, buffer + HEADER_LEN, state_len)?;
+    let ret = slice(memory, buffer + HEADER_LEN + state_len, ret_len)?;
+    Ok((state, ret))
+}
+
+// Encodes the length of a value written to a call buffer
+fn length<S: Store>(len: usize) -> Result<[u8; 4], VMError<S>> {
+    u32::try_from(len)
+        .map(u32::to_le_bytes)
+        .map_err(|_| VMError::CallBufferTooLarge(len))
+}
+
+/// Returns the cost of handling `len` bytes at `cost` gas per byte
+pub fn per_byte(cost: Gas, len: usize) -> Gas {
+    cost.saturating_mul(len as Gas)
 }
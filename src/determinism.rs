@@ -0,0 +1,283 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use canonical::Store;
+use parity_wasm::elements::{self, Instruction, ValueType};
+
+use crate::VMError;
+
+// Bit patterns of the canonical quiet NaNs.
+const CANONICAL_NAN_F32: u32 = 0x7fc0_0000;
+const CANONICAL_NAN_F64: u64 = 0x7ff8_0000_0000_0000;
+
+/// Rejects modules using floating-point instructions or values, whose results
+/// may differ across platforms.
+///
+/// Besides instructions, float types are rejected in function signatures,
+/// locals, globals and block results.
+pub fn check_floats<S: Store>(
+    module: &elements::Module,
+) -> Result<(), VMError<S>> {
+    let float = |what: String| Err(VMError::FloatInstruction(what));
+
+    let types = module
+        .type_section()
+        .map(|section| section.types())
+        .unwrap_or(&[]);
+
+    for (i, elements::Type::Function(ty)) in types.iter().enumerate() {
+        if ty
+            .params()
+            .iter()
+            .chain(ty.return_type().iter())
+            .any(is_float_type)
+        {
+            return float(format!("type[{}]: {:?}", i, ty));
+        }
+    }
+
+    let imports = module
+        .import_section()
+        .map(|section| section.entries())
+        .unwrap_or(&[]);
+
+    for entry in imports {
+        if let elements::External::Global(ty) = entry.external() {
+            if is_float_type(&ty.content_type()) {
+                return float(format!(
+                    "import {}::{}: {}",
+                    entry.module(),
+                    entry.field(),
+                    ty.content_type()
+                ));
+            }
+        }
+    }
+
+    let globals = module
+        .global_section()
+        .map(|section| section.entries())
+        .unwrap_or(&[]);
+
+    for (i, global) in globals.iter().enumerate() {
+        let ty = global.global_type().content_type();
+        if is_float_type(&ty) {
+            return float(format!("global[{}]: {}", i, ty));
+        }
+    }
+
+    let imported = module.import_count(elements::ImportCountType::Function);
+
+    let bodies = module
+        .code_section()
+        .map(|section| section.bodies())
+        .unwrap_or(&[]);
+
+    for (i, body) in bodies.iter().enumerate() {
+        if let Some(local) = body
+            .locals()
+            .iter()
+            .find(|local| is_float_type(&local.value_type()))
+        {
+            return float(format!(
+                "func[{}]: local {}",
+                imported + i,
+                local.value_type()
+            ));
+        }
+
+        if let Some(instruction) =
+            body.code().elements().iter().find(|i| is_float(i))
+        {
+            return float(format!("func[{}]: {}", imported + i, instruction));
+        }
+    }
+
+    Ok(())
+}
+
+/// Canonicalizes the NaNs produced by floating-point arithmetic, whose bit
+/// patterns may differ across platforms.
+///
+/// Two functions are appended to the module, replacing any NaN on top of the
+/// stack by the canonical one, and called after every instruction that may
+/// produce a NaN.
+pub fn canonicalize_nans(mut module: elements::Module) -> elements::Module {
+    let uses_floats = module
+        .code_section()
+        .map(|section| {
+            section.bodies().iter().any(|body| {
+                body.code().elements().iter().any(|i| nan_type(i).is_some())
+            })
+        })
+        .unwrap_or(false);
+
+    if !uses_floats {
+        return module;
+    }
+
+    let imported = module.import_count(elements::ImportCountType::Function);
+    let defined = module
+        .function_section()
+        .map(|section| section.entries().len())
+        .unwrap_or(0);
+    let f32_index = (imported + defined) as u32;
+    let f64_index = f32_index + 1;
+
+    for body in module
+        .code_section_mut()
+        .expect("module has code")
+        .bodies_mut()
+    {
+        let instructions = body.code_mut().elements_mut();
+        let mut canonicalized = Vec::with_capacity(instructions.len());
+
+        for instruction in instructions.drain(..) {
+            let ty = nan_type(&instruction);
+            canonicalized.push(instruction);
+
+            match ty {
+                Some(ValueType::F32) => {
+                    canonicalized.push(Instruction::Call(f32_index))
+                }
+                Some(ValueType::F64) => {
+                    canonicalized.push(Instruction::Call(f64_index))
+                }
+                _ => (),
+            }
+        }
+
+        *instructions = canonicalized;
+    }
+
+    for (ty, nan, eq) in &[
+        (
+            ValueType::F32,
+            Instruction::F32Const(CANONICAL_NAN_F32),
+            Instruction::F32Eq,
+        ),
+        (
+            ValueType::F64,
+            Instruction::F64Const(CANONICAL_NAN_F64),
+            Instruction::F64Eq,
+        ),
+    ] {
+        let types = module.type_section_mut().expect("module has code");
+        types.types_mut().push(elements::Type::Function(
+            elements::FunctionType::new(vec![*ty], Some(*ty)),
+        ));
+        let type_index = (types.types().len() - 1) as u32;
+
+        module
+            .function_section_mut()
+            .expect("module has code")
+            .entries_mut()
+            .push(elements::Func::new(type_index));
+
+        // a NaN is the only value not equal to itself
+        module
+            .code_section_mut()
+            .expect("module has code")
+            .bodies_mut()
+            .push(elements::FuncBody::new(
+                vec![],
+                elements::Instructions::new(vec![
+                    Instruction::GetLocal(0),
+                    nan.clone(),
+                    Instruction::GetLocal(0),
+                    Instruction::GetLocal(0),
+                    eq.clone(),
+                    Instruction::Select,
+                    Instruction::End,
+                ]),
+            ));
+    }
+
+    module
+}
+
+// Returns the type of the float produced by an instruction that may produce
+// a NaN with a platform-dependent bit pattern. Sign manipulations only
+// operate on bits, and are deterministic.
+fn nan_type(instruction: &Instruction) -> Option<ValueType> {
+    use Instruction::*;
+
+    match instruction {
+        F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt | F32Add
+        | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32DemoteF64 => {
+            Some(ValueType::F32)
+        }
+        F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt | F64Add
+        | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64PromoteF32 => {
+            Some(ValueType::F64)
+        }
+        _ => None,
+    }
+}
+
+fn is_float_type(ty: &ValueType) -> bool {
+    matches!(ty, ValueType::F32 | ValueType::F64)
+}
+
+fn is_float(instruction: &Instruction) -> bool {
+    use Instruction::*;
+
+    if let Block(ty) | Loop(ty) | If(ty) = instruction {
+        return match ty {
+            elements::BlockType::Value(ty) => is_float_type(ty),
+            elements::BlockType::NoResult => false,
+        };
+    }
+
+    nan_type(instruction).is_some()
+        || matches!(
+            instruction,
+            F32Load(..)
+                | F64Load(..)
+                | F32Store(..)
+                | F64Store(..)
+                | F32Const(_)
+                | F64Const(_)
+                | F32Abs
+                | F32Neg
+                | F32Copysign
+                | F64Abs
+                | F64Neg
+                | F64Copysign
+                | F32Eq
+                | F32Ne
+                | F32Lt
+                | F32Gt
+                | F32Le
+                | F32Ge
+                | F64Eq
+                | F64Ne
+                | F64Lt
+                | F64Gt
+                | F64Le
+                | F64Ge
+                | I32TruncSF32
+                | I32TruncUF32
+                | I32TruncSF64
+                | I32TruncUF64
+                | I64TruncSF32
+                | I64TruncUF32
+                | I64TruncSF64
+                | I64TruncUF64
+                | F32ConvertSI32
+                | F32ConvertUI32
+                | F32ConvertSI64
+                | F32ConvertUI64
+                | F64ConvertSI32
+                | F64ConvertUI32
+                | F64ConvertSI64
+                | F64ConvertUI64
+                | I32ReinterpretF32
+                | I64ReinterpretF64
+                | F32ReinterpretI32
+                | F64ReinterpretI64
+        )
+}
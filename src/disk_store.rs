@@ -0,0 +1,441 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use canonical::{
+    ByteSink, ByteSource, Canon, Id32, Ident, InvalidEncoding, Store,
+};
+use fs2::FileExt;
+
+/// Name of the log the values are appended to
+const LOG_FILE: &str = "values.log";
+/// Name of the file holding the committed root
+const ROOT_FILE: &str = "ROOT";
+/// Name of the file a new root is written to before replacing the old one
+const ROOT_TMP_FILE: &str = "ROOT.tmp";
+/// Name of the file locked by the store holding the directory
+const LOCK_FILE: &str = "LOCK";
+
+/// Length of the header of a log record, holding the ident of the value and
+/// its length as a little-endian `u32`
+const RECORD_HEADER_LEN: usize = 32 + 4;
+
+/// Length of the root file, holding the root ident and the length of the log
+/// it commits to as a little-endian `u64`
+const ROOT_LEN: usize = 32 + 8;
+
+/// The errors that can happen while reading from or writing to a
+/// [`DiskStore`]
+#[derive(Debug)]
+pub enum DiskError {
+    /// No value is stored under the requested ident
+    MissingValue,
+    /// A value could not be decoded
+    InvalidEncoding,
+    /// The log is corrupted before the length committed by the root
+    Corrupted,
+    /// The directory is held by another open store
+    Locked,
+    /// A failed write could not be rolled back, the store must be reopened
+    Broken,
+    /// The underlying files could not be accessed
+    Io(io::Error),
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiskError::MissingValue => write!(f, "Missing value"),
+            DiskError::InvalidEncoding => write!(f, "Invalid encoding"),
+            DiskError::Corrupted => write!(f, "Corrupted log"),
+            DiskError::Locked => write!(f, "Store directory locked"),
+            DiskError::Broken => write!(f, "Store broken by a failed write"),
+            DiskError::Io(e) => write!(f, "IO error ({})", e),
+        }
+    }
+}
+
+impl InvalidEncoding for DiskError {
+    fn invalid_encoding() -> Self {
+        DiskError::InvalidEncoding
+    }
+}
+
+impl From<io::Error> for DiskError {
+    fn from(e: io::Error) -> Self {
+        DiskError::Io(e)
+    }
+}
+
+/// A [`Store`] persisting its values to a directory
+///
+/// Values are appended to a write-ahead log, each in a single write, and read
+/// back from it through an index of their offsets kept in memory. The log is
+/// only guaranteed to be on disk once a root has been set with
+/// [`DiskStore::set_root`], which syncs it before atomically replacing the
+/// previous root along with the length of the log it commits to.
+///
+/// On opening, records written after the committed length and left
+/// incomplete by a crash are discarded, while a log corrupted before it
+/// fails with [`DiskError::Corrupted`]. A write that fails is rolled back by
+/// truncating the log to its previous length, and if that fails too, the
+/// store refuses further writes with [`DiskError::Broken`].
+///
+/// The directory is locked while the store is open, a second store opening
+/// it fails with [`DiskError::Locked`]. The log is never compacted, values
+/// are limited to `u32::MAX` bytes, and accesses to the log are serialized.
+///
+/// A store created with `default` is not backed by any directory, and keeps
+/// its values in memory only.
+#[derive(Clone, Default)]
+pub struct DiskStore(Arc<Mutex<Inner>>);
+
+enum Inner {
+    Memory {
+        values: HashMap<Id32, Vec<u8>>,
+        root: Option<Id32>,
+    },
+    Disk(Log),
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Inner::Memory {
+            values: HashMap::new(),
+            root: None,
+        }
+    }
+}
+
+struct Log {
+    dir: PathBuf,
+    file: File,
+    // offset in the log and length of every value
+    index: HashMap<Id32, (u64, u32)>,
+    // length of the log written so far
+    len: u64,
+    root: Option<Id32>,
+    broken: bool,
+    // kept open to hold the lock on the directory
+    _lock: File,
+}
+
+/// A [`Store`] keeping track of the root of the state committed last, for it
+/// to be restored after a restart
+///
+/// Used by [`NetworkState::commit_root`] and [`NetworkState::restore`].
+///
+/// [`NetworkState::commit_root`]: crate::NetworkState::commit_root
+/// [`NetworkState::restore`]: crate::NetworkState::restore
+pub trait RootStore: Store {
+    /// Returns the root set last, if any
+    fn root(&self) -> Option<Self::Ident>;
+
+    /// Makes all the values written so far durable, and then atomically
+    /// replaces the root of the store by `root`
+    fn set_root(&self, root: &Self::Ident) -> Result<(), Self::Error>;
+}
+
+impl RootStore for DiskStore {
+    fn root(&self) -> Option<Id32> {
+        DiskStore::root(self)
+    }
+
+    fn set_root(&self, root: &Id32) -> Result<(), DiskError> {
+        DiskStore::set_root(self, root)
+    }
+}
+
+impl fmt::Debug for DiskStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &*self.0.lock().expect("Poisoned lock") {
+            Inner::Memory { .. } => write!(f, "DiskStore(None)"),
+            Inner::Disk(log) => write!(f, "DiskStore({:?})", log.dir),
+        }
+    }
+}
+
+impl DiskStore {
+    /// Opens the store kept in the directory at `path`, creating it if it
+    /// does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DiskError> {
+        let dir = path.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(dir.join(LOCK_FILE))?;
+
+        lock.try_lock_exclusive().map_err(|e| {
+            if e.kind() == fs2::lock_contended_error().kind() {
+                DiskError::Locked
+            } else {
+                DiskError::Io(e)
+            }
+        })?;
+
+        // a root left over by a crash before replacing the previous one was
+        // never committed
+        let _ = fs::remove_file(dir.join(ROOT_TMP_FILE));
+
+        let (root, committed_len) = match fs::read(dir.join(ROOT_FILE)) {
+            Ok(bytes) if bytes.len() == ROOT_LEN => {
+                let len = u64::from_le_bytes(
+                    bytes[32..].try_into().expect("root holds a length"),
+                );
+                (Some(ident(&bytes[..32])), len)
+            }
+            Ok(_) => return Err(DiskError::InvalidEncoding),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (None, 0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(dir.join(LOG_FILE))?;
+
+        let file_len = file.metadata()?.len();
+        let (index, len) = replay(&mut file, file_len)?;
+
+        if len < committed_len {
+            return Err(DiskError::Corrupted);
+        }
+
+        // only the records written after the last commit may be torn
+        if len < file_len {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+
+        Ok(DiskStore(Arc::new(Mutex::new(Inner::Disk(Log {
+            dir,
+            file,
+            index,
+            len,
+            root,
+            broken: false,
+            _lock: lock,
+        })))))
+    }
+
+    /// Returns the root set last, if any
+    pub fn root(&self) -> Option<Id32> {
+        match &*self.0.lock().expect("Poisoned lock") {
+            Inner::Memory { root, .. } => *root,
+            Inner::Disk(log) => log.root,
+        }
+    }
+
+    /// Makes all the values written so far durable, and then atomically
+    /// replaces the root of the store by `root`
+    pub fn set_root(&self, root: &Id32) -> Result<(), DiskError> {
+        let mut inner = self.0.lock().expect("Poisoned lock");
+
+        let log = match &mut *inner {
+            Inner::Memory { root: current, .. } => {
+                *current = Some(*root);
+                return Ok(());
+            }
+            Inner::Disk(log) => log,
+        };
+
+        if log.broken {
+            return Err(DiskError::Broken);
+        }
+
+        log.file.sync_all()?;
+
+        let mut bytes = [0u8; ROOT_LEN];
+        bytes[..32].copy_from_slice(root.as_ref());
+        bytes[32..].copy_from_slice(&log.len.to_le_bytes());
+
+        let tmp = log.dir.join(ROOT_TMP_FILE);
+
+        let mut file = File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+
+        fs::rename(&tmp, log.dir.join(ROOT_FILE))?;
+        // make the rename itself durable
+        File::open(&log.dir)?.sync_all()?;
+
+        log.root = Some(*root);
+        Ok(())
+    }
+
+    // Returns the bytes of the value stored under `id`
+    fn value(&self, id: &Id32) -> Result<Vec<u8>, DiskError> {
+        let mut inner = self.0.lock().expect("Poisoned lock");
+
+        match &mut *inner {
+            Inner::Memory { values, .. } => {
+                values.get(id).cloned().ok_or(DiskError::MissingValue)
+            }
+            Inner::Disk(log) => {
+                let (offset, len) =
+                    *log.index.get(id).ok_or(DiskError::MissingValue)?;
+
+                let mut bytes = vec![0u8; len as usize];
+                log.file.seek(SeekFrom::Start(offset))?;
+                log.file.read_exact(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl Log {
+    // Appends a record holding `bytes` under `id` to the log, truncating the
+    // log back to its previous length if the write fails.
+    fn append(&mut self, id: &Id32, bytes: &[u8]) -> Result<(), DiskError> {
+        if self.broken {
+            return Err(DiskError::Broken);
+        }
+
+        let len = bytes.len().try_into().map_err(|_| {
+            DiskError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "value too large",
+            ))
+        })?;
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + bytes.len());
+        record.extend_from_slice(id.as_ref());
+        record.extend_from_slice(&u32::to_le_bytes(len));
+        record.extend_from_slice(bytes);
+
+        let written = self
+            .file
+            .seek(SeekFrom::Start(self.len))
+            .and_then(|_| self.file.write_all(&record));
+
+        if let Err(e) = written {
+            if self.file.set_len(self.len).is_err() {
+                self.broken = true;
+            }
+            return Err(e.into());
+        }
+
+        self.index
+            .insert(*id, (self.len + RECORD_HEADER_LEN as u64, len));
+        self.len += record.len() as u64;
+        Ok(())
+    }
+}
+
+impl Store for DiskStore {
+    type Ident = Id32;
+    type Error = DiskError;
+
+    fn fetch(
+        &self,
+        id: &Self::Ident,
+        into: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let value = self.value(id)?;
+
+        let len = value.len().min(into.len());
+        into[..len].copy_from_slice(&value[..len]);
+        Ok(())
+    }
+
+    fn get<T: Canon<Self>>(&self, id: &Self::Ident) -> Result<T, Self::Error> {
+        // the lock is released before decoding, which may fetch nested values
+        let bytes = self.value(id)?;
+
+        let mut source = ByteSource::new(&bytes[..], self);
+        Canon::<Self>::read(&mut source)
+    }
+
+    fn put<T: Canon<Self>>(&self, t: &T) -> Result<Self::Ident, Self::Error> {
+        let mut bytes = vec![0u8; t.encoded_len()];
+
+        let mut sink = ByteSink::new(&mut bytes[..], self);
+        Canon::<Self>::write(t, &mut sink)?;
+
+        self.put_raw(&bytes)
+    }
+
+    fn put_raw(&self, bytes: &[u8]) -> Result<Self::Ident, Self::Error> {
+        let id = Id32::from_bytes(bytes);
+
+        let mut inner = self.0.lock().expect("Poisoned lock");
+
+        match &mut *inner {
+            Inner::Memory { values, .. } => {
+                values.entry(id).or_insert_with(|| bytes.to_vec());
+            }
+            Inner::Disk(log) => {
+                if !log.index.contains_key(&id) {
+                    log.append(&id, bytes)?;
+                }
+            }
+        }
+
+        Ok(id)
+    }
+}
+
+// Reads the records of a log of `file_len` bytes, returning the offsets and
+// lengths of the values they hold along with the length of the log up to the
+// first incomplete or corrupted record.
+fn replay(
+    file: &mut File,
+    file_len: u64,
+) -> Result<(HashMap<Id32, (u64, u32)>, u64), DiskError> {
+    let mut index = HashMap::new();
+    let mut offset = 0;
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    let mut value = vec![];
+
+    while file_len - offset >= RECORD_HEADER_LEN as u64 {
+        reader.read_exact(&mut header)?;
+
+        let len = u32::from_le_bytes(
+            header[32..].try_into().expect("header holds a length"),
+        );
+
+        let start = offset + RECORD_HEADER_LEN as u64;
+        if file_len - start < len as u64 {
+            break;
+        }
+
+        value.resize(len as usize, 0);
+        reader.read_exact(&mut value)?;
+
+        // idents are hashes of the values, doubling as checksums
+        let id = ident(&header[..32]);
+        if Id32::from_bytes(&value) != id {
+            break;
+        }
+
+        index.insert(id, (start, len));
+        offset = start + len as u64;
+    }
+
+    Ok((index, offset))
+}
+
+// Builds an ident from its raw bytes
+fn ident(bytes: &[u8]) -> Id32 {
+    let mut id = Id32::default();
+    id.as_mut().copy_from_slice(bytes);
+    id
+}
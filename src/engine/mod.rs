@@ -0,0 +1,112 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! The engines executing contracts.
+//!
+//! Contracts are instrumented before being handed to an engine, so that gas
+//! metering and stack height limiting are done by the contract code itself,
+//! and are identical whatever the engine.
+
+use std::rc::Rc;
+
+use canonical::Store;
+use parity_wasm::elements;
+
+use crate::call_context::CallContext;
+use crate::VMError;
+
+mod wasmi_engine;
+#[cfg(feature = "wasmtime")]
+mod wasmtime_engine;
+
+/// The engines contracts can be executed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EngineKind {
+    /// The wasmi interpreter
+    Wasmi,
+    /// The wasmtime compiler, available with the `wasmtime` feature
+    #[cfg(feature = "wasmtime")]
+    Wasmtime,
+}
+
+impl Default for EngineKind {
+    fn default() -> Self {
+        EngineKind::Wasmi
+    }
+}
+
+impl EngineKind {
+    /// Returns the engine of this kind
+    pub(crate) fn engine<S: Store>(self) -> Box<dyn Engine<S>> {
+        match self {
+            EngineKind::Wasmi => Box::new(wasmi_engine::WasmiEngine),
+            #[cfg(feature = "wasmtime")]
+            EngineKind::Wasmtime => {
+                Box::new(wasmtime_engine::WasmtimeEngine::default())
+            }
+        }
+    }
+}
+
+/// An engine compiling instrumented modules
+pub(crate) trait Engine<S: Store> {
+    /// Compiles an instrumented contract `module`
+    fn compile(
+        &self,
+        module: elements::Module,
+    ) -> Result<Rc<dyn Module<S>>, VMError<S>>;
+}
+
+/// A compiled contract module
+pub(crate) trait Module<S: Store> {
+    /// Creates a new instance of the module, with the ABI functions of the
+    /// VM as imports
+    fn instantiate(&self) -> Result<Rc<dyn Instance<S>>, VMError<S>>;
+}
+
+/// An instance of a contract module
+pub(crate) trait Instance<S: Store> {
+    /// Invokes the exported `entrypoint` with the offset of the call
+    /// `buffer`, with the host calls it makes handled by `context`
+    fn invoke(
+        &self,
+        entrypoint: &str,
+        buffer: u32,
+        context: &mut CallContext<S>,
+    ) -> Result<(), VMError<S>>;
+
+    /// Returns the stack height reached by the instance
+    fn stack_height(&self) -> u32;
+
+    /// Sets the stack height the instance starts counting from
+    fn set_stack_height(&self, height: u32) -> Result<(), VMError<S>>;
+
+    /// Returns true if the instance trapped on growing its memory beyond the
+    /// maximum
+    fn memory_limit_exceeded(&self) -> bool;
+
+    /// Grows the memory of the instance by `pages`, returning its previous
+    /// size in pages
+    fn grow_memory(&self, pages: u32) -> Result<u32, VMError<S>>;
+
+    /// Gives `closure` access to the memory of the instance
+    fn with_memory(&self, closure: &mut dyn FnMut(&mut [u8]));
+}
+
+impl<S: Store> dyn Instance<S> {
+    /// Runs `closure` on the memory of the instance, returning its result
+    pub(crate) fn memory<R, C: FnOnce(&mut [u8]) -> R>(&self, closure: C) -> R {
+        let mut closure = Some(closure);
+        let mut result = None;
+
+        self.with_memory(&mut |memory| {
+            let closure = closure.take().expect("memory is accessed once");
+            result = Some(closure(memory));
+        });
+
+        result.expect("memory is accessed once")
+    }
+}
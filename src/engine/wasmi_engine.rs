@@ -0,0 +1,211 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::rc::Rc;
+
+use canonical::Store;
+use parity_wasm::elements;
+use wasmi::memory_units::Pages;
+use wasmi::{
+    Externals, ImportsBuilder, MemoryRef, ModuleRef, NopExternals, RuntimeArgs,
+    RuntimeValue, Trap, TrapKind,
+};
+
+use super::{Engine, Instance, Module};
+use crate::call_context::{CallContext, Invoke, StandardABI};
+use crate::instrument::{
+    MEMORY_LIMIT_EXCEEDED_EXPORT, STACK_HEIGHT_GET_EXPORT,
+    STACK_HEIGHT_SET_EXPORT,
+};
+use crate::{InvalidModule, VMError};
+
+/// Executes contracts with the wasmi interpreter
+pub(crate) struct WasmiEngine;
+
+impl<S: Store> Engine<S> for WasmiEngine {
+    fn compile(
+        &self,
+        module: elements::Module,
+    ) -> Result<Rc<dyn Module<S>>, VMError<S>> {
+        Ok(Rc::new(WasmiModule(
+            wasmi::Module::from_parity_wasm_module(module)?,
+        )))
+    }
+}
+
+struct WasmiModule(wasmi::Module);
+
+impl<S: Store> Module<S> for WasmiModule {
+    fn instantiate(&self) -> Result<Rc<dyn Instance<S>>, VMError<S>> {
+        let resolver = StandardABI::<S>::default();
+        let imports = ImportsBuilder::new()
+            .with_resolver("env", &resolver)
+            .with_resolver("canon", &resolver);
+
+        // contracts with a start function are rejected on deploy
+        let instance =
+            wasmi::ModuleInstance::new(&self.0, &imports)?.assert_no_start();
+
+        let memory = match instance.export_by_name("memory") {
+            Some(wasmi::ExternVal::Memory(memory)) => memory,
+            _ => return Err(VMError::MemoryNotFound),
+        };
+
+        for export in &[
+            STACK_HEIGHT_GET_EXPORT,
+            STACK_HEIGHT_SET_EXPORT,
+            MEMORY_LIMIT_EXCEEDED_EXPORT,
+        ] {
+            match instance.export_by_name(export) {
+                Some(wasmi::ExternVal::Func(_)) => (),
+                _ => {
+                    return Err(VMError::InvalidWASMModule(
+                        InvalidModule::Instrumentation,
+                    ))
+                }
+            }
+        }
+
+        Ok(Rc::new(WasmiInstance { instance, memory }))
+    }
+}
+
+struct WasmiInstance {
+    instance: ModuleRef,
+    memory: MemoryRef,
+}
+
+impl<S: Store> Instance<S> for WasmiInstance {
+    fn invoke(
+        &self,
+        entrypoint: &str,
+        buffer: u32,
+        context: &mut CallContext<S>,
+    ) -> Result<(), VMError<S>> {
+        self.instance
+            .invoke_export(
+                entrypoint,
+                &[RuntimeValue::I32(buffer as i32)],
+                context,
+            )
+            .map(|_| ())
+            .map_err(|error| host_error(context, error))
+    }
+
+    fn stack_height(&self) -> u32 {
+        match self.instance.invoke_export(
+            STACK_HEIGHT_GET_EXPORT,
+            &[],
+            &mut NopExternals,
+        ) {
+            Ok(Some(RuntimeValue::I32(height))) => height as u32,
+            _ => 0,
+        }
+    }
+
+    fn set_stack_height(&self, height: u32) -> Result<(), VMError<S>> {
+        self.instance.invoke_export(
+            STACK_HEIGHT_SET_EXPORT,
+            &[RuntimeValue::I32(height as i32)],
+            &mut NopExternals,
+        )?;
+        Ok(())
+    }
+
+    fn memory_limit_exceeded(&self) -> bool {
+        matches!(
+            self.instance.invoke_export(
+                MEMORY_LIMIT_EXCEEDED_EXPORT,
+                &[],
+                &mut NopExternals,
+            ),
+            Ok(Some(RuntimeValue::I32(1)))
+        )
+    }
+
+    fn grow_memory(&self, pages: u32) -> Result<u32, VMError<S>> {
+        let Pages(previous) = self.memory.grow(Pages(pages as usize))?;
+        Ok(previous as u32)
+    }
+
+    fn with_memory(&self, closure: &mut dyn FnMut(&mut [u8])) {
+        self.memory.with_direct_access_mut(closure)
+    }
+}
+
+/// Converts an error returned by wasmi into a `VMError`, surfacing the error
+/// raised by the failing host call, if any, as is.
+fn host_error<S: Store>(
+    context: &mut CallContext<S>,
+    error: wasmi::Error,
+) -> VMError<S> {
+    if error.as_host_error().is_some() {
+        if let Some(e) = context.take_host_error() {
+            return e;
+        }
+    }
+
+    if let wasmi::Error::Trap(trap) = &error {
+        match trap.kind() {
+            TrapKind::StackOverflow => return VMError::StackOverflow,
+            // the stack height limiter traps with `unreachable` after having
+            // bumped the height over the limit
+            TrapKind::Unreachable if context.stack_overflowed() => {
+                return VMError::StackOverflow
+            }
+            // so does a failed memory growth, after having set a flag
+            TrapKind::Unreachable if context.memory_limit_exceeded() => {
+                return VMError::MemoryLimitExceeded
+            }
+            _ => (),
+        }
+    }
+
+    error.into()
+}
+
+/// Trap raised by a failing host call.
+///
+/// The actual error is kept by the `CallContext` until wasmi unwinds the trap
+/// back to the caller of the contract.
+#[derive(Debug)]
+struct HostCallFailed;
+
+impl std::fmt::Display for HostCallFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Host call failed")
+    }
+}
+
+impl wasmi::HostError for HostCallFailed {}
+
+/// Convenience function to construct host traps
+fn host_trap() -> Trap {
+    Trap::new(TrapKind::Host(Box::new(HostCallFailed)))
+}
+
+impl<'a, S> Externals for CallContext<'a, S>
+where
+    S: Store,
+{
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        match StandardABI::invoke(self, index, args) {
+            Ok(ok) => Ok(ok),
+            Err(e) => {
+                if let VMError::Trap(t) = e {
+                    Err(t)
+                } else {
+                    self.fail(e);
+                    Err(host_trap())
+                }
+            }
+        }
+    }
+}
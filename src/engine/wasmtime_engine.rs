@@ -0,0 +1,363 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use canonical::Store;
+use parity_wasm::elements::{self, External, ValueType};
+use wasmi::{RuntimeArgs, RuntimeValue, TrapKind};
+use wasmtime::{Extern, Func, FuncType, Memory, Trap, TrapCode, Val, ValType};
+
+use super::{Engine, Instance, Module};
+use crate::call_context::{CallContext, Invoke, StandardABI};
+use crate::instrument::{
+    MEMORY_LIMIT_EXCEEDED_EXPORT, STACK_HEIGHT_GET_EXPORT,
+    STACK_HEIGHT_SET_EXPORT,
+};
+use crate::{InvalidModule, VMError};
+
+/// Executes contracts with the wasmtime compiler
+#[derive(Default)]
+pub(crate) struct WasmtimeEngine {
+    engine: wasmtime::Engine,
+}
+
+impl<S: Store> Engine<S> for WasmtimeEngine {
+    fn compile(
+        &self,
+        module: elements::Module,
+    ) -> Result<Rc<dyn Module<S>>, VMError<S>> {
+        let types = module
+            .type_section()
+            .map(|section| section.types())
+            .unwrap_or(&[]);
+
+        let mut imports = vec![];
+
+        for entry in module
+            .import_section()
+            .map(|section| section.entries())
+            .unwrap_or(&[])
+        {
+            let unknown = || {
+                VMError::InvalidWASMModule(InvalidModule::UnknownImport {
+                    module: entry.module().into(),
+                    field: entry.field().into(),
+                })
+            };
+
+            let (index, _) =
+                StandardABI::<S>::import(entry.field()).ok_or_else(unknown)?;
+
+            let ty = match entry.external() {
+                External::Function(ty) => match types.get(*ty as usize) {
+                    Some(elements::Type::Function(ty)) => FuncType::new(
+                        ty.params().iter().map(val_type),
+                        ty.return_type().map(val_type),
+                    ),
+                    None => return Err(unknown()),
+                },
+                _ => return Err(unknown()),
+            };
+
+            imports.push((index, ty));
+        }
+
+        let bytecode = parity_wasm::serialize(module).map_err(|_| {
+            VMError::InvalidWASMModule(InvalidModule::Instrumentation)
+        })?;
+
+        let module = wasmtime::Module::new(&self.engine, bytecode)
+            .map_err(|e| VMError::WasmtimeError(e.to_string()))?;
+
+        Ok(Rc::new(WasmtimeModule { module, imports }))
+    }
+}
+
+struct WasmtimeModule {
+    module: wasmtime::Module,
+    // index in the ABI and signature of every imported function, in order
+    imports: Vec<(usize, FuncType)>,
+}
+
+impl<S: Store> Module<S> for WasmtimeModule {
+    fn instantiate(&self) -> Result<Rc<dyn Instance<S>>, VMError<S>> {
+        let store = wasmtime::Store::new(self.module.engine());
+        let host = HostCell::default();
+
+        let imports: Vec<Extern> = self
+            .imports
+            .iter()
+            .map(|(index, ty)| {
+                host_func(&store, host.clone(), *index, ty.clone()).into()
+            })
+            .collect();
+
+        let instance = wasmtime::Instance::new(&store, &self.module, &imports)
+            .map_err(|e| VMError::WasmtimeError(e.to_string()))?;
+
+        let memory = instance
+            .get_memory("memory")
+            .ok_or(VMError::MemoryNotFound)?;
+
+        let accessor = |name| {
+            instance.get_func(name).ok_or_else(|| {
+                VMError::InvalidWASMModule(InvalidModule::Instrumentation)
+            })
+        };
+        let get_stack_height = accessor(STACK_HEIGHT_GET_EXPORT)?;
+        let set_stack_height = accessor(STACK_HEIGHT_SET_EXPORT)?;
+        let memory_limit_exceeded = accessor(MEMORY_LIMIT_EXCEEDED_EXPORT)?;
+
+        Ok(Rc::new(WasmtimeInstance {
+            instance,
+            memory,
+            get_stack_height,
+            set_stack_height,
+            memory_limit_exceeded,
+            host,
+        }))
+    }
+}
+
+struct WasmtimeInstance {
+    instance: wasmtime::Instance,
+    memory: Memory,
+    get_stack_height: Func,
+    set_stack_height: Func,
+    memory_limit_exceeded: Func,
+    host: HostCell,
+}
+
+impl<S: Store> Instance<S> for WasmtimeInstance {
+    fn invoke(
+        &self,
+        entrypoint: &str,
+        buffer: u32,
+        context: &mut CallContext<S>,
+    ) -> Result<(), VMError<S>> {
+        let func = self.instance.get_func(entrypoint).ok_or_else(|| {
+            VMError::WasmtimeError(format!("Export {} not found", entrypoint))
+        })?;
+
+        let previous = self.host.replace(Some(erase(context)));
+        let result = func.call(&[Val::I32(buffer as i32)]);
+        self.host.set(previous);
+
+        result.map(|_| ()).map_err(|trap| trap_error(context, trap))
+    }
+
+    fn stack_height(&self) -> u32 {
+        match self.get_stack_height.call(&[]).as_deref() {
+            Ok([Val::I32(height)]) => *height as u32,
+            _ => 0,
+        }
+    }
+
+    fn set_stack_height(&self, height: u32) -> Result<(), VMError<S>> {
+        self.set_stack_height
+            .call(&[Val::I32(height as i32)])
+            .map(|_| ())
+            .map_err(|e| VMError::WasmtimeError(e.to_string()))
+    }
+
+    fn memory_limit_exceeded(&self) -> bool {
+        matches!(
+            self.memory_limit_exceeded.call(&[]).as_deref(),
+            Ok([Val::I32(1)])
+        )
+    }
+
+    fn grow_memory(&self, pages: u32) -> Result<u32, VMError<S>> {
+        self.memory
+            .grow(pages)
+            .map_err(|e| VMError::WasmtimeError(e.to_string()))
+    }
+
+    fn with_memory(&self, closure: &mut dyn FnMut(&mut [u8])) {
+        // Safety: `data_unchecked_mut` requires that the memory is neither
+        // grown nor accessed by wasm, nor borrowed elsewhere, while the slice
+        // is alive. The slice does not outlive the call to `closure`, which
+        // only gets the slice and not the instance, so it cannot run wasm
+        // code or grow the memory. The host only reaches the memory through
+        // here, and while the contract is not running or is waiting on a
+        // host call, so no other borrow of it is alive.
+        closure(unsafe { self.memory.data_unchecked_mut() })
+    }
+}
+
+/// The host functions provided to contracts, with the type of `CallContext`
+/// erased.
+trait Host {
+    /// Calls the ABI function at `index`, returning `None` if it failed
+    fn call(
+        &mut self,
+        index: usize,
+        args: &[RuntimeValue],
+    ) -> Option<Option<RuntimeValue>>;
+}
+
+impl<'a, S> Host for CallContext<'a, S>
+where
+    S: Store,
+{
+    fn call(
+        &mut self,
+        index: usize,
+        args: &[RuntimeValue],
+    ) -> Option<Option<RuntimeValue>> {
+        match StandardABI::invoke(self, index, RuntimeArgs::from(args)) {
+            Ok(ret) => Some(ret),
+            Err(e) => {
+                self.fail(e);
+                None
+            }
+        }
+    }
+}
+
+// The host of the invocation in progress, set for its duration only. Host
+// functions are created along with the instance, before the `CallContext`
+// invoking it is known.
+type HostCell = Rc<Cell<Option<*mut dyn Host>>>;
+
+// Erases the lifetime of `host`, for it to be kept in a `HostCell`.
+//
+// The returned pointer must only be dereferenced while `host` is borrowed.
+// `WasmtimeInstance::invoke` upholds this: it holds the mutable borrow of
+// the `CallContext` for the whole invocation, sets the pointer right before
+// calling into wasm and restores the previous one right after, and does not
+// use the context in between. Host functions only dereference the pointer
+// while they are called by that invocation, synchronously and on the same
+// thread, the `HostCell` being neither `Send` nor `Sync`, so the reference
+// they create is the only live access to the context.
+fn erase<'a>(host: &'a mut (dyn Host + 'a)) -> *mut (dyn Host + 'static) {
+    // Safety: the transmute only changes the lifetime of the trait object,
+    // the layout of both pointers being the same, and the pointer is only
+    // dereferenced while `host` is borrowed, as stated above.
+    unsafe { std::mem::transmute(host) }
+}
+
+fn host_func(
+    store: &wasmtime::Store,
+    host: HostCell,
+    index: usize,
+    ty: FuncType,
+) -> Func {
+    Func::new(store, ty, move |_, params, results| {
+        let host = host.get().ok_or_else(|| Trap::new(HOST_CALL_FAILED))?;
+
+        let args = params
+            .iter()
+            .map(runtime_value)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| Trap::new(HOST_CALL_FAILED))?;
+
+        // Safety: the pointer is only set while the invocation calling this
+        // function borrows the context it points to, see `erase`
+        let host = unsafe { &mut *host };
+
+        match host.call(index, &args) {
+            Some(Some(ret)) => results[0] = val(ret),
+            Some(None) => (),
+            None => return Err(Trap::new(HOST_CALL_FAILED)),
+        }
+        Ok(())
+    })
+}
+
+/// Message of the trap raised by a failing host call.
+///
+/// The actual error is kept by the `CallContext` until wasmtime unwinds the
+/// trap back to the caller of the contract.
+const HOST_CALL_FAILED: &str = "Host call failed";
+
+/// Converts a trap raised by wasmtime into a `VMError`, surfacing the error
+/// raised by the failing host call, if any, as is.
+///
+/// Traps are converted to the same errors the wasmi engine returns, so that
+/// both engines give the same results for the same failure.
+fn trap_error<S: Store>(
+    context: &mut CallContext<S>,
+    trap: Trap,
+) -> VMError<S> {
+    if let Some(e) = context.take_host_error() {
+        return e;
+    }
+
+    match trap.trap_code() {
+        Some(TrapCode::StackOverflow) => VMError::StackOverflow,
+        // the stack height limiter traps with `unreachable` after having
+        // bumped the height over the limit
+        Some(TrapCode::UnreachableCodeReached)
+            if context.stack_overflowed() =>
+        {
+            VMError::StackOverflow
+        }
+        // so does a failed memory growth, after having set a flag
+        Some(TrapCode::UnreachableCodeReached)
+            if context.memory_limit_exceeded() =>
+        {
+            VMError::MemoryLimitExceeded
+        }
+        code => match code.and_then(trap_kind) {
+            Some(kind) => {
+                VMError::WasmiError(wasmi::Error::Trap(wasmi::Trap::new(kind)))
+            }
+            None => VMError::WasmtimeError(trap.to_string()),
+        },
+    }
+}
+
+// Returns the kind of the trap wasmi raises for the same failure as the
+// wasmtime trap `code`, if any.
+fn trap_kind(code: TrapCode) -> Option<TrapKind> {
+    match code {
+        TrapCode::StackOverflow => Some(TrapKind::StackOverflow),
+        TrapCode::MemoryOutOfBounds | TrapCode::HeapMisaligned => {
+            Some(TrapKind::MemoryAccessOutOfBounds)
+        }
+        TrapCode::TableOutOfBounds => Some(TrapKind::TableAccessOutOfBounds),
+        TrapCode::IndirectCallToNull => Some(TrapKind::ElemUninitialized),
+        TrapCode::BadSignature => Some(TrapKind::UnexpectedSignature),
+        TrapCode::IntegerDivisionByZero => Some(TrapKind::DivisionByZero),
+        // wasmi reports the overflow of a signed division as a failed
+        // conversion as well
+        TrapCode::IntegerOverflow | TrapCode::BadConversionToInteger => {
+            Some(TrapKind::InvalidConversionToInt)
+        }
+        TrapCode::UnreachableCodeReached => Some(TrapKind::Unreachable),
+        _ => None,
+    }
+}
+
+fn val_type(ty: &ValueType) -> ValType {
+    match ty {
+        ValueType::I32 => ValType::I32,
+        ValueType::I64 => ValType::I64,
+        ValueType::F32 => ValType::F32,
+        ValueType::F64 => ValType::F64,
+    }
+}
+
+// The ABI only deals with integers
+fn runtime_value(val: &Val) -> Option<RuntimeValue> {
+    match val {
+        Val::I32(val) => Some(RuntimeValue::I32(*val)),
+        Val::I64(val) => Some(RuntimeValue::I64(*val)),
+        _ => None,
+    }
+}
+
+fn val(value: RuntimeValue) -> Val {
+    match value {
+        RuntimeValue::I32(value) => Val::I32(value),
+        RuntimeValue::I64(value) => Val::I64(value),
+        RuntimeValue::F32(value) => Val::F32(value.to_bits()),
+        RuntimeValue::F64(value) => Val::F64(value.to_bits()),
+    }
+}
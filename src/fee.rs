@@ -0,0 +1,100 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use canonical::Store;
+
+use crate::gas::Gas;
+use crate::VMError;
+
+/// The gas limit and gas price a payer commits to for a transaction
+///
+/// Prices and fees are expressed in the smallest unit of the balances kept
+/// by the [`NetworkState`].
+///
+/// [`NetworkState`]: crate::NetworkState
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fee {
+    gas_limit: Gas,
+    gas_price: u64,
+}
+
+impl Fee {
+    /// Creates a new `Fee` with given gas limit and gas price
+    pub fn new(gas_limit: Gas, gas_price: u64) -> Self {
+        Fee {
+            gas_limit,
+            gas_price,
+        }
+    }
+
+    /// Returns the maximum amount of gas the transaction can spend
+    pub fn gas_limit(&self) -> Gas {
+        self.gas_limit
+    }
+
+    /// Returns the price paid per unit of gas spent
+    pub fn gas_price(&self) -> u64 {
+        self.gas_price
+    }
+
+    /// Returns the fee for spending `gas`, or `None` on overflow
+    pub fn cost(&self, gas: Gas) -> Option<u64> {
+        gas.checked_mul(self.gas_price)
+    }
+
+    /// Returns the fee for spending the whole gas limit, which is reserved
+    /// from the payer before the transaction is executed
+    pub fn max_cost(&self) -> Option<u64> {
+        self.cost(self.gas_limit)
+    }
+}
+
+/// The outcome of a transaction whose fee has been settled
+pub struct Receipt<R, S>
+where
+    S: Store,
+{
+    gas_spent: Gas,
+    fee: u64,
+    result: Result<R, VMError<S>>,
+}
+
+impl<R, S> Receipt<R, S>
+where
+    S: Store,
+{
+    pub(crate) fn new(
+        gas_spent: Gas,
+        fee: u64,
+        result: Result<R, VMError<S>>,
+    ) -> Self {
+        Receipt {
+            gas_spent,
+            fee,
+            result,
+        }
+    }
+
+    /// Returns the gas spent by the transaction
+    pub fn gas_spent(&self) -> Gas {
+        self.gas_spent
+    }
+
+    /// Returns the fee charged to the payer
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    /// Returns the result of the transaction
+    pub fn result(&self) -> &Result<R, VMError<S>> {
+        &self.result
+    }
+
+    /// Consumes the receipt, returning the result of the transaction
+    pub fn into_result(self) -> Result<R, VMError<S>> {
+        self.result
+    }
+}
@@ -0,0 +1,393 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::convert::TryFrom;
+
+use canonical::Store;
+use parity_wasm::elements;
+use pwasm_utils::rules;
+
+use crate::determinism;
+use crate::validate::{self, InvalidModule};
+use crate::{Schedule, VMError};
+
+/// Name of the exported function returning the stack height of an instance.
+pub const STACK_HEIGHT_GET_EXPORT: &str = "__stack_height_get";
+
+/// Name of the exported function setting the stack height of an instance.
+pub const STACK_HEIGHT_SET_EXPORT: &str = "__stack_height_set";
+
+/// Name of the exported function returning whether an instance trapped on
+/// growing its memory beyond the maximum.
+pub const MEMORY_LIMIT_EXCEEDED_EXPORT: &str = "__memory_limit_exceeded";
+
+/// Name of the import charging the gas of a metered block.
+pub const CHARGE_GAS_IMPORT: &str = "charge_gas";
+
+/// Validates the contract `bytecode`, and injects gas metering, stack height
+/// limiting and memory limiting into it, according to the `schedule`.
+///
+/// The resulting module calls the [`CHARGE_GAS_IMPORT`] at the start of every
+/// metered block and before growing its memory, so that execution is always
+/// bounded by the active `GasMeter`, and traps whenever its stack height exceeds
+/// `Schedule::max_stack_height` or its memory would grow beyond
+/// `Schedule::max_memory_pages`. The stack height can be read and set through
+/// the [`STACK_HEIGHT_GET_EXPORT`] and [`STACK_HEIGHT_SET_EXPORT`] functions,
+/// so that it can be carried over nested calls, and whether the memory limit
+/// was hit is returned by the [`MEMORY_LIMIT_EXCEEDED_EXPORT`] function.
+///
+/// The resulting module is left to an engine to compile.
+pub fn instrument<S: Store>(
+    bytecode: &[u8],
+    schedule: &Schedule,
+) -> Result<elements::Module, VMError<S>> {
+    let module = validate::validate(bytecode, schedule)?;
+
+    let module = limit_memory(module, schedule.max_memory_pages)?;
+
+    let regular_op_cost =
+        u32::try_from(schedule.regular_op_cost).unwrap_or(u32::MAX);
+    let grow_mem_cost =
+        u32::try_from(schedule.grow_mem_cost).unwrap_or(u32::MAX);
+    let rules = rules::Set::new(regular_op_cost, Default::default())
+        .with_grow_cost(grow_mem_cost);
+
+    let defined = module
+        .function_section()
+        .map(|section| section.entries().len())
+        .unwrap_or(0);
+
+    let module = if schedule.allow_floats {
+        determinism::canonicalize_nans(module)
+    } else {
+        module
+    };
+
+    let module = pwasm_utils::inject_gas_counter(module, &rules)
+        .map_err(|_| failed())?;
+    let module = charge_per_function(module, defined)?;
+
+    let module = pwasm_utils::stack_height::inject_limiter(
+        module,
+        schedule.max_stack_height,
+    )
+    .map_err(|_| failed())?;
+
+    let module = export_stack_height_accessors(module)?;
+    trap_on_failed_grow(module)
+}
+
+fn failed<S: Store>() -> VMError<S> {
+    VMError::InvalidWASMModule(InvalidModule::Instrumentation)
+}
+
+// The gas counter imports `gas(cost)` from `env` as the last function import,
+// shifting the indices of the `defined` functions of the original module by
+// one. The import is replaced by `charge_gas(cost, function)`, passing the
+// index the calling function had in the original module, or -1 for the
+// functions added by the instrumentation, so that the gas spent can be
+// attributed to it.
+fn charge_per_function<S: Store>(
+    mut module: elements::Module,
+    defined: usize,
+) -> Result<elements::Module, VMError<S>> {
+    use elements::{External, ImportEntry, Instruction, ValueType};
+
+    let gas_index =
+        module.import_count(elements::ImportCountType::Function) as u32 - 1;
+
+    let types = module.type_section_mut().ok_or_else(failed)?;
+    types.types_mut().push(elements::Type::Function(
+        elements::FunctionType::new(vec![ValueType::I32, ValueType::I32], None),
+    ));
+    let type_index = (types.types().len() - 1) as u32;
+
+    let import = module
+        .import_section_mut()
+        .and_then(|section| section.entries_mut().last_mut())
+        .ok_or_else(failed)?;
+
+    if import.module() != "env" || import.field() != "gas" {
+        return Err(failed());
+    }
+
+    *import = ImportEntry::new(
+        "env".into(),
+        CHARGE_GAS_IMPORT.into(),
+        External::Function(type_index),
+    );
+
+    if let Some(section) = module.code_section_mut() {
+        for (i, body) in section.bodies_mut().iter_mut().enumerate() {
+            let function = if i < defined {
+                (gas_index as usize + i) as i32
+            } else {
+                -1
+            };
+
+            let instructions = body.code_mut().elements_mut();
+            let mut charged = Vec::with_capacity(instructions.len());
+
+            for instruction in instructions.drain(..) {
+                if matches!(instruction, Instruction::Call(i) if i == gas_index)
+                {
+                    charged.push(Instruction::I32Const(function));
+                }
+                charged.push(instruction);
+            }
+
+            *instructions = charged;
+        }
+    }
+
+    Ok(module)
+}
+
+// Rejects modules declaring more memory than `max_pages`, and caps the
+// maximum of the memory they declare to `max_pages`.
+fn limit_memory<S: Store>(
+    mut module: elements::Module,
+    max_pages: u32,
+) -> Result<elements::Module, VMError<S>> {
+    if let Some(section) = module.memory_section_mut() {
+        for entry in section.entries_mut() {
+            let initial = entry.limits().initial();
+            let maximum = entry.limits().maximum().unwrap_or(max_pages);
+
+            if initial > max_pages {
+                return Err(VMError::InvalidWASMModule(
+                    InvalidModule::TooManyMemoryPages {
+                        initial,
+                        max: max_pages,
+                    },
+                ));
+            }
+
+            *entry = elements::MemoryType::new(
+                initial,
+                Some(maximum.min(max_pages)),
+            );
+        }
+    }
+
+    Ok(module)
+}
+
+// A `memory.grow` beyond the memory maximum returns -1 instead of trapping.
+// Every `memory.grow` is redirected to an appended function that traps in
+// that case instead, after setting a flag in an appended global, for the
+// host to tell the trap apart from other ones through an exported function
+// reading it.
+fn trap_on_failed_grow<S: Store>(
+    mut module: elements::Module,
+) -> Result<elements::Module, VMError<S>> {
+    use elements::{BlockType, ExportEntry, Instruction, Internal, ValueType};
+
+    let flag = (module.import_count(elements::ImportCountType::Global)
+        + module
+            .global_section()
+            .map(|section| section.entries().len())
+            .unwrap_or(0)) as u32;
+
+    module
+        .global_section_mut()
+        .ok_or_else(failed)?
+        .entries_mut()
+        .push(elements::GlobalEntry::new(
+            elements::GlobalType::new(ValueType::I32, true),
+            elements::InitExpr::new(vec![
+                Instruction::I32Const(0),
+                Instruction::End,
+            ]),
+        ));
+
+    let types = module.type_section_mut().ok_or_else(failed)?;
+    types.types_mut().push(elements::Type::Function(
+        elements::FunctionType::new(vec![], Some(ValueType::I32)),
+    ));
+    let get_type = (types.types().len() - 1) as u32;
+
+    let get_index = (module.import_count(elements::ImportCountType::Function)
+        + module
+            .function_section()
+            .map(|section| section.entries().len())
+            .unwrap_or(0)) as u32;
+
+    module
+        .function_section_mut()
+        .ok_or_else(failed)?
+        .entries_mut()
+        .push(elements::Func::new(get_type));
+    module
+        .code_section_mut()
+        .ok_or_else(failed)?
+        .bodies_mut()
+        .push(elements::FuncBody::new(
+            vec![],
+            elements::Instructions::new(vec![
+                Instruction::GetGlobal(flag),
+                Instruction::End,
+            ]),
+        ));
+    module
+        .export_section_mut()
+        .ok_or_else(failed)?
+        .entries_mut()
+        .push(ExportEntry::new(
+            MEMORY_LIMIT_EXCEEDED_EXPORT.into(),
+            Internal::Function(get_index),
+        ));
+
+    let grows = module
+        .code_section()
+        .map(|section| {
+            section.bodies().iter().any(|body| {
+                body.code()
+                    .elements()
+                    .iter()
+                    .any(|i| matches!(i, Instruction::GrowMemory(_)))
+            })
+        })
+        .unwrap_or(false);
+
+    if !grows {
+        return Ok(module);
+    }
+
+    let imported = module.import_count(elements::ImportCountType::Function);
+    let defined = module
+        .function_section()
+        .map(|section| section.entries().len())
+        .unwrap_or(0);
+    let guard_index = (imported + defined) as u32;
+
+    for body in module
+        .code_section_mut()
+        .expect("module has code")
+        .bodies_mut()
+    {
+        for instruction in body.code_mut().elements_mut() {
+            if let Instruction::GrowMemory(_) = instruction {
+                *instruction = Instruction::Call(guard_index);
+            }
+        }
+    }
+
+    let types = module.type_section_mut().expect("module has code");
+    types.types_mut().push(elements::Type::Function(
+        elements::FunctionType::new(vec![ValueType::I32], Some(ValueType::I32)),
+    ));
+    let type_index = (types.types().len() - 1) as u32;
+
+    module
+        .function_section_mut()
+        .expect("module has code")
+        .entries_mut()
+        .push(elements::Func::new(type_index));
+
+    module
+        .code_section_mut()
+        .expect("module has code")
+        .bodies_mut()
+        .push(elements::FuncBody::new(
+            vec![elements::Local::new(1, ValueType::I32)],
+            elements::Instructions::new(vec![
+                Instruction::GetLocal(0),
+                Instruction::GrowMemory(0),
+                Instruction::TeeLocal(1),
+                Instruction::I32Const(-1),
+                Instruction::I32Eq,
+                Instruction::If(BlockType::NoResult),
+                Instruction::I32Const(1),
+                Instruction::SetGlobal(flag),
+                Instruction::Unreachable,
+                Instruction::End,
+                Instruction::GetLocal(1),
+                Instruction::End,
+            ]),
+        ));
+
+    Ok(module)
+}
+
+// The stack height limiter keeps track of the height in a global it appends
+// to the global section. The global is mutable, and wasm forbids exporting
+// mutable globals, so two functions reading and writing it are appended and
+// exported instead, for the host to read and initialize the height.
+fn export_stack_height_accessors<S: Store>(
+    mut module: elements::Module,
+) -> Result<elements::Module, VMError<S>> {
+    use elements::{ExportEntry, Instruction, Internal, ValueType};
+
+    let imported = module.import_count(elements::ImportCountType::Global);
+    let defined = module
+        .global_section()
+        .map(|section| section.entries().len())
+        .unwrap_or(0);
+
+    if defined == 0 {
+        return Err(failed());
+    }
+
+    let global = (imported + defined - 1) as u32;
+
+    let types = module.type_section_mut().ok_or_else(failed)?;
+    types.types_mut().push(elements::Type::Function(
+        elements::FunctionType::new(vec![], Some(ValueType::I32)),
+    ));
+    types.types_mut().push(elements::Type::Function(
+        elements::FunctionType::new(vec![ValueType::I32], None),
+    ));
+    let get_type = (types.types().len() - 2) as u32;
+    let set_type = get_type + 1;
+
+    let functions = module.import_count(elements::ImportCountType::Function)
+        + module
+            .function_section()
+            .map(|section| section.entries().len())
+            .unwrap_or(0);
+    let get_index = functions as u32;
+    let set_index = get_index + 1;
+
+    let entries = module
+        .function_section_mut()
+        .ok_or_else(failed)?
+        .entries_mut();
+    entries.push(elements::Func::new(get_type));
+    entries.push(elements::Func::new(set_type));
+
+    let bodies = module.code_section_mut().ok_or_else(failed)?.bodies_mut();
+    bodies.push(elements::FuncBody::new(
+        vec![],
+        elements::Instructions::new(vec![
+            Instruction::GetGlobal(global),
+            Instruction::End,
+        ]),
+    ));
+    bodies.push(elements::FuncBody::new(
+        vec![],
+        elements::Instructions::new(vec![
+            Instruction::GetLocal(0),
+            Instruction::SetGlobal(global),
+            Instruction::End,
+        ]),
+    ));
+
+    let exports = module
+        .export_section_mut()
+        .ok_or_else(failed)?
+        .entries_mut();
+    exports.push(ExportEntry::new(
+        STACK_HEIGHT_GET_EXPORT.into(),
+        Internal::Function(get_index),
+    ));
+    exports.push(ExportEntry::new(
+        STACK_HEIGHT_SET_EXPORT.into(),
+        Internal::Function(set_index),
+    ));
+
+    Ok(module)
+}
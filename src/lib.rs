@@ -15,19 +15,42 @@ use std::{fmt, io};
 use canonical::Store;
 use failure::Fail;
 
+mod block;
+mod cache;
 mod call_context;
 mod contract;
+mod determinism;
+mod disk_store;
+mod engine;
+mod fee;
 mod gas;
+mod instrument;
+mod merkle;
 mod ops;
+mod profiler;
+mod proof;
+mod reentrancy;
 mod resolver;
+mod schedule;
 mod state;
+mod validate;
 
 pub use dusk_abi;
 
+pub use block::{Block, BlockTransaction, FinalizedBlock};
+pub use cache::{CacheStats, DEFAULT_CACHE_CAPACITY};
 pub use call_context::StandardABI;
 pub use contract::{Contract, ContractId};
+pub use disk_store::{DiskError, DiskStore, RootStore};
+pub use engine::EngineKind;
+pub use fee::{Fee, Receipt};
 pub use gas::{Gas, GasMeter};
-pub use state::NetworkState;
+pub use profiler::{GasProfiler, ProfileFrame};
+pub use proof::{verify_proof, ContractProof};
+pub use reentrancy::ReentrancyPolicy;
+pub use schedule::Schedule;
+pub use state::{Checkpoint, NetworkState};
+pub use validate::InvalidModule;
 
 #[derive(Fail)]
 /// The errors that can happen while executing the VM
@@ -53,20 +76,51 @@ where
     ContractReturn(i32, i32),
     /// Contract execution ran out of gas
     OutOfGas,
+    /// Contract uses a floating-point instruction, which is not allowed by the
+    /// schedule
+    FloatInstruction(String),
+    /// Contract execution exceeded the maximum stack height
+    StackOverflow,
+    /// Contract calls were nested deeper than the maximum call depth
+    CallDepthExceeded,
+    /// Contract tried to mutate the state while being queried
+    MutationInQuery,
+    /// A block was started at a height not above the one of the state
+    InvalidBlockHeight(u64),
+    /// The checkpoint has been released
+    UnknownCheckpoint,
     /// Not enough funds for call
     NotEnoughFunds,
     /// Contract could not be found in the state
     UnknownContract,
+    /// Contract was called while having a frame on the call stack, which is
+    /// not permitted by the reentrancy policy
+    Reentrancy,
     /// WASM threw an error
     WASMError(failure::Error),
     /// wasmi trap triggered
     Trap(wasmi::Trap),
     /// Wasmi threw an error
     WasmiError(wasmi::Error),
+    /// Wasmtime threw an error
+    #[cfg(feature = "wasmtime")]
+    WasmtimeError(String),
+    /// Contract accessed memory outside of its bounds
+    MemoryAccessOutOfBounds,
+    /// Contract tried to grow its memory beyond the maximum of the schedule
+    MemoryLimitExceeded,
+    /// The state and argument of a call, of the given total length, do not
+    /// fit in the memory of the contract
+    CallBufferTooLarge(usize),
+    /// Contract tried to get a value longer than the schedule allows from
+    /// the store
+    StoreValueTooLarge,
+    /// The host panicked while executing a contract, with the panic message
+    HostPanic(String),
     /// Input output error
     IOError(io::Error),
-    /// Invalid WASM Module
-    InvalidWASMModule,
+    /// Invalid WASM Module, with the reason it was rejected
+    InvalidWASMModule(InvalidModule),
     /// Error propagated from underlying store
     StoreError(S::Error),
 }
@@ -116,15 +170,43 @@ impl<S: Store> fmt::Display for VMError<S> {
             }
             VMError::ContractReturn(_, _) => write!(f, "Contract Return")?,
             VMError::OutOfGas => write!(f, "Out of Gas error")?,
+            VMError::FloatInstruction(instruction) => {
+                write!(f, "Floating point instruction ({})", instruction)?
+            }
+            VMError::StackOverflow => write!(f, "Stack overflow")?,
+            VMError::CallDepthExceeded => write!(f, "Call depth exceeded")?,
+            VMError::MutationInQuery => {
+                write!(f, "State mutation attempted in a query")?
+            }
+            VMError::InvalidBlockHeight(height) => {
+                write!(f, "Invalid block height {}", height)?
+            }
+            VMError::UnknownCheckpoint => write!(f, "Unknown checkpoint")?,
             VMError::NotEnoughFunds => write!(f, "Not enough funds error")?,
             VMError::WASMError(e) => write!(f, "WASM Error ({:?})", e)?,
             VMError::MemoryNotFound => write!(f, "Memory not found")?,
             VMError::InvalidABICall => write!(f, "Invalid ABI Call")?,
+            VMError::MemoryAccessOutOfBounds => {
+                write!(f, "Memory access out of bounds")?
+            }
+            VMError::MemoryLimitExceeded => write!(f, "Memory limit exceeded")?,
+            VMError::CallBufferTooLarge(len) => {
+                write!(f, "Call buffer too large ({} bytes)", len)?
+            }
+            VMError::StoreValueTooLarge => write!(f, "Store value too large")?,
+            VMError::HostPanic(message) => {
+                write!(f, "Host panic \"{}\"", message)?
+            }
             VMError::IOError(e) => write!(f, "Input/Output Error ({:?})", e)?,
             VMError::Trap(e) => write!(f, "Trap ({:?})", e)?,
             VMError::WasmiError(e) => write!(f, "WASMI Error ({:?})", e)?,
+            #[cfg(feature = "wasmtime")]
+            VMError::WasmtimeError(e) => write!(f, "Wasmtime Error ({})", e)?,
             VMError::UnknownContract => write!(f, "Unknown Contract")?,
-            VMError::InvalidWASMModule => write!(f, "Invalid WASM module")?,
+            VMError::Reentrancy => write!(f, "Reentrancy")?,
+            VMError::InvalidWASMModule(reason) => {
+                write!(f, "Invalid WASM module ({})", reason)?
+            }
             VMError::StoreError(e) => write!(f, "Store error {:?}", e)?,
         }
         Ok(())
@@ -136,91 +218,3 @@ impl<S: Store> fmt::Debug for VMError<S> {
         write!(f, "{}", self)
     }
 }
-
-/// Definition of the cost schedule and other parameterizations for wasm vm.
-#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
-#[derive(Clone, PartialEq, Eq)]
-pub struct Schedule {
-    /// Version of the schedule.
-    pub version: u32,
-
-    /// Cost of putting a byte of code into storage.
-    pub put_code_per_byte_cost: Gas,
-
-    /// Gas cost of a growing memory by single page.
-    pub grow_mem_cost: Gas,
-
-    /// Gas cost of a regular operation.
-    pub regular_op_cost: Gas,
-
-    /// Gas cost per one byte returned.
-    pub return_data_per_byte_cost: Gas,
-
-    /// Gas cost to deposit an event; the per-byte portion.
-    pub event_data_per_byte_cost: Gas,
-
-    /// Gas cost to deposit an event; the cost per topic.
-    pub event_per_topic_cost: Gas,
-
-    /// Gas cost to deposit an event; the base.
-    pub event_base_cost: Gas,
-
-    /// Base gas cost to call into a contract.
-    pub call_base_cost: Gas,
-
-    /// Base gas cost to instantiate a contract.
-    pub instantiate_base_cost: Gas,
-
-    /// Gas cost per one byte read from the sandbox memory.
-    pub sandbox_data_read_cost: Gas,
-
-    /// Gas cost per one byte written to the sandbox memory.
-    pub sandbox_data_write_cost: Gas,
-
-    /// The maximum number of topics supported by an event.
-    pub max_event_topics: u32,
-
-    /// Maximum allowed stack height.
-    ///
-    /// See https://wiki.parity.io/WebAssembly-StackHeight to find out
-    /// how the stack frame cost is calculated.
-    pub max_stack_height: u32,
-
-    /// Maximum number of memory pages allowed for a contract.
-    pub max_memory_pages: u32,
-
-    /// Maximum allowed size of a declared table.
-    pub max_table_size: u32,
-
-    /// Whether the `ext_println` function is allowed to be used contracts.
-    /// MUST only be enabled for `dev` chains, NOT for production chains
-    pub enable_println: bool,
-
-    /// The maximum length of a subject used for PRNG generation.
-    pub max_subject_len: u32,
-}
-
-impl Default for Schedule {
-    fn default() -> Schedule {
-        Schedule {
-            version: 0,
-            put_code_per_byte_cost: 1,
-            grow_mem_cost: 1,
-            regular_op_cost: 1,
-            return_data_per_byte_cost: 1,
-            event_data_per_byte_cost: 1,
-            event_per_topic_cost: 1,
-            event_base_cost: 1,
-            call_base_cost: 135,
-            instantiate_base_cost: 175,
-            sandbox_data_read_cost: 1,
-            sandbox_data_write_cost: 1,
-            max_event_topics: 4,
-            max_stack_height: 64 * 1024,
-            max_memory_pages: 16,
-            max_table_size: 16 * 1024,
-            enable_println: false,
-            max_subject_len: 32,
-        }
-    }
-}
@@ -0,0 +1,503 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Persistent binary tries over contract ids, hashed into the ident of a
+//! store.
+//!
+//! Tries are compressed: a branch only exists where the keys below it
+//! differ, and records the first bit they differ at, its split bit, the keys
+//! with that bit unset being on its left. Split bits strictly increase from
+//! the root down, and the shape of a trie only depends on its keys. Leaves
+//! commit to their key, and leaves, branches and the state root are hashed
+//! with distinct prefixes so that none of them can be passed off as another.
+
+use std::rc::Rc;
+
+use canonical::{Canon, Ident, InvalidEncoding, Sink, Source, Store};
+use dusk_abi::ContractId;
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+const STATE: u8 = 2;
+
+// Tags of the encoded tries and of their nodes
+const EMPTY_TAG: u8 = 0;
+const ROOT_TAG: u8 = 1;
+const LEAF_TAG: u8 = 1;
+const BRANCH_TAG: u8 = 2;
+
+/// Number of bits of a key
+pub(crate) const KEY_BITS: u16 = 256;
+
+/// Hashes the content of a leaf
+pub(crate) fn leaf<I: Ident>(bytes: &[u8]) -> I {
+    hash(LEAF, &[bytes])
+}
+
+/// Hashes the two children of a branch splitting keys at bit `split`
+pub(crate) fn node<I: Ident>(split: u16, left: &I, right: &I) -> I {
+    hash(NODE, &[&split.to_le_bytes(), left.as_ref(), right.as_ref()])
+}
+
+/// Everything the root of a state commits to besides its contracts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Metadata<I> {
+    pub block_height: u64,
+    pub balances_root: I,
+    pub schedules_hash: I,
+    pub reentrancy: u8,
+}
+
+// Manual implementation of `Canon`, since the idents of a store are not
+// `Canon` themselves.
+impl<S> Canon<S> for Metadata<S::Ident>
+where
+    S: Store,
+{
+    fn write(&self, sink: &mut impl Sink<S>) -> Result<(), S::Error> {
+        self.block_height.write(sink)?;
+        write_ident::<S>(&self.balances_root, sink);
+        write_ident::<S>(&self.schedules_hash, sink);
+        self.reentrancy.write(sink)
+    }
+
+    fn read(source: &mut impl Source<S>) -> Result<Self, S::Error> {
+        Ok(Metadata {
+            block_height: u64::read(source)?,
+            balances_root: read_ident::<S>(source),
+            schedules_hash: read_ident::<S>(source),
+            reentrancy: u8::read(source)?,
+        })
+    }
+
+    fn encoded_len(&self) -> usize {
+        Canon::<S>::encoded_len(&self.block_height)
+            + ident_len::<S>()
+            + ident_len::<S>()
+            + Canon::<S>::encoded_len(&self.reentrancy)
+    }
+}
+
+impl<I: Ident> Metadata<I> {
+    /// Hashes the root of the contracts trie along with the metadata into
+    /// the root of the state
+    pub(crate) fn root(&self, contracts_root: &I) -> I {
+        hash(
+            STATE,
+            &[
+                &self.block_height.to_le_bytes(),
+                contracts_root.as_ref(),
+                self.balances_root.as_ref(),
+                self.schedules_hash.as_ref(),
+                &[self.reentrancy],
+            ],
+        )
+    }
+}
+
+/// Returns the leaf of a contract, committing to its id, the hash of its
+/// bytecode and the hash of its state
+pub(crate) fn contract_leaf<I: Ident>(
+    id: &ContractId,
+    code_hash: &I,
+    state_hash: &I,
+) -> I {
+    leaf(&[id.as_bytes(), code_hash.as_ref(), state_hash.as_ref()].concat())
+}
+
+/// Returns the leaf of the balance of an account
+pub(crate) fn balance_leaf<I: Ident>(id: &ContractId, balance: u64) -> I {
+    leaf(&[id.as_bytes(), &balance.to_le_bytes()[..]].concat())
+}
+
+/// Returns whether the bit at `index` of `key` is set, counting from the
+/// most significant bit of its first byte
+pub(crate) fn bit(key: &ContractId, index: u16) -> bool {
+    key.as_bytes()[index as usize / 8] & (0x80 >> (index % 8)) != 0
+}
+
+/// Returns the root of a trie in which the walk for `key` passes the
+/// branches of `path`, given as their split bit and the hash of the child
+/// not taken from the root down, and ends at `leaf`
+///
+/// Returns `None` if the split bits do not strictly increase.
+pub(crate) fn root_from_path<I: Ident>(
+    key: &ContractId,
+    leaf: I,
+    path: &[(u16, I)],
+) -> Option<I> {
+    let increasing = path.windows(2).all(|pair| pair[0].0 < pair[1].0);
+    let in_range = path.iter().all(|(split, _)| *split < KEY_BITS);
+
+    if !increasing || !in_range {
+        return None;
+    }
+
+    Some(path.iter().rev().fold(leaf, |child, (split, sibling)| {
+        if bit(key, *split) {
+            node(*split, sibling, &child)
+        } else {
+            node(*split, &child, sibling)
+        }
+    }))
+}
+
+/// A persistent binary trie, mapping contract ids to the hashes of their
+/// leaves
+///
+/// Clones share their nodes, and an insertion only copies the branches on
+/// the path to the inserted leaf.
+#[derive(Clone)]
+pub(crate) struct Trie<I>(Option<Rc<Node<I>>>);
+
+enum Node<I> {
+    Leaf {
+        key: ContractId,
+        hash: I,
+    },
+    Branch {
+        split: u16,
+        hash: I,
+        left: Rc<Node<I>>,
+        right: Rc<Node<I>>,
+    },
+}
+
+impl<I> Default for Trie<I> {
+    fn default() -> Self {
+        Trie(None)
+    }
+}
+
+impl<I: Ident> Trie<I> {
+    /// Returns the root hash of the trie, the default ident if it is empty
+    pub(crate) fn root(&self) -> I {
+        self.0.as_ref().map(|node| *node.hash()).unwrap_or_default()
+    }
+
+    /// Returns a trie mapping `key` to `leaf`, replacing its previous leaf
+    /// if any
+    pub(crate) fn insert(&self, key: &ContractId, leaf: I) -> Self {
+        Trie(Some(match &self.0 {
+            None => Rc::new(Node::Leaf {
+                key: *key,
+                hash: leaf,
+            }),
+            Some(root) => {
+                let split = first_difference(root.closest(key), key);
+                root.insert(key, leaf, split)
+            }
+        }))
+    }
+
+    /// Returns the key and leaf of every leaf of the trie
+    pub(crate) fn leaves(&self) -> Vec<(ContractId, I)> {
+        let mut leaves = vec![];
+        let mut nodes: Vec<&Node<I>> = self.0.iter().map(|n| &**n).collect();
+
+        while let Some(node) = nodes.pop() {
+            match node {
+                Node::Leaf { key, hash } => leaves.push((*key, *hash)),
+                Node::Branch { left, right, .. } => {
+                    nodes.push(right);
+                    nodes.push(left);
+                }
+            }
+        }
+
+        leaves
+    }
+
+    /// Walks the trie for `key`, returning the split bit and the hash of the
+    /// child not taken of every branch passed from the root down, along
+    /// with the key of the leaf reached, if the trie is not empty
+    ///
+    /// The leaf reached holds `key` if the trie holds it at all.
+    pub(crate) fn path(
+        &self,
+        key: &ContractId,
+    ) -> (Vec<(u16, I)>, Option<ContractId>) {
+        let mut path = vec![];
+        let mut node = match &self.0 {
+            Some(root) => root,
+            None => return (path, None),
+        };
+
+        loop {
+            match &**node {
+                Node::Leaf { key: reached, .. } => {
+                    return (path, Some(*reached))
+                }
+                Node::Branch {
+                    split, left, right, ..
+                } => {
+                    let (next, sibling) = if bit(key, *split) {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                    path.push((*split, *sibling.hash()));
+                    node = next;
+                }
+            }
+        }
+    }
+}
+
+impl<I: Ident> Node<I> {
+    fn hash(&self) -> &I {
+        match self {
+            Node::Leaf { hash, .. } | Node::Branch { hash, .. } => hash,
+        }
+    }
+
+    fn branch(split: u16, left: Rc<Self>, right: Rc<Self>) -> Rc<Self> {
+        Rc::new(Node::Branch {
+            split,
+            hash: node(split, left.hash(), right.hash()),
+            left,
+            right,
+        })
+    }
+
+    // Returns the key of the leaf the walk for `key` ends at, which shares
+    // the longest prefix with `key` of all the keys below the node.
+    fn closest(&self, key: &ContractId) -> &ContractId {
+        match self {
+            Node::Leaf { key, .. } => key,
+            Node::Branch {
+                split, left, right, ..
+            } => {
+                if bit(key, *split) {
+                    right.closest(key)
+                } else {
+                    left.closest(key)
+                }
+            }
+        }
+    }
+
+    // Returns the key of the leftmost leaf below the node, which agrees with
+    // all the others on the bits before the split of the node.
+    fn any_key(&self) -> &ContractId {
+        match self {
+            Node::Leaf { key, .. } => key,
+            Node::Branch { left, .. } => left.any_key(),
+        }
+    }
+
+    // Inserts the `leaf` of `key` below the node, where it differs from the
+    // closest key at bit `split`, or replaces the leaf of the same key if
+    // `split` is `None`.
+    fn insert(
+        self: &Rc<Self>,
+        key: &ContractId,
+        leaf: I,
+        split: Option<u16>,
+    ) -> Rc<Self> {
+        match &**self {
+            Node::Branch {
+                split: branch_split,
+                left,
+                right,
+                ..
+            } if split.map_or(true, |split| *branch_split < split) => {
+                if bit(key, *branch_split) {
+                    Self::branch(
+                        *branch_split,
+                        left.clone(),
+                        right.insert(key, leaf, split),
+                    )
+                } else {
+                    Self::branch(
+                        *branch_split,
+                        left.insert(key, leaf, split),
+                        right.clone(),
+                    )
+                }
+            }
+            _ => {
+                let new = Rc::new(Node::Leaf {
+                    key: *key,
+                    hash: leaf,
+                });
+
+                match split {
+                    None => new,
+                    Some(split) if bit(key, split) => {
+                        Self::branch(split, self.clone(), new)
+                    }
+                    Some(split) => Self::branch(split, new, self.clone()),
+                }
+            }
+        }
+    }
+}
+
+// Returns the first bit `a` and `b` differ at, if any.
+fn first_difference(a: &ContractId, b: &ContractId) -> Option<u16> {
+    a.as_bytes()
+        .iter()
+        .zip(b.as_bytes())
+        .position(|(a, b)| a != b)
+        .map(|index| {
+            let diff = a.as_bytes()[index] ^ b.as_bytes()[index];
+            index as u16 * 8 + diff.leading_zeros() as u16
+        })
+}
+
+// Manual implementation of `Canon`, storing every node of the trie on its
+// own and encoding the ident of the root only, so that committing a trie
+// only adds the nodes that changed to the store.
+impl<S> Canon<S> for Trie<S::Ident>
+where
+    S: Store,
+{
+    fn write(&self, sink: &mut impl Sink<S>) -> Result<(), S::Error> {
+        match &self.0 {
+            None => EMPTY_TAG.write(sink),
+            Some(root) => {
+                ROOT_TAG.write(sink)?;
+                let ident = sink.recur(&**root)?;
+                write_ident::<S>(&ident, sink);
+                Ok(())
+            }
+        }
+    }
+
+    fn read(source: &mut impl Source<S>) -> Result<Self, S::Error> {
+        match u8::read(source)? {
+            EMPTY_TAG => Ok(Trie(None)),
+            ROOT_TAG => {
+                let ident = read_ident::<S>(source);
+                let root = source.store().get(&ident)?;
+                Ok(Trie(Some(Rc::new(root))))
+            }
+            _ => Err(InvalidEncoding::invalid_encoding()),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        match &self.0 {
+            None => Canon::<S>::encoded_len(&EMPTY_TAG),
+            Some(_) => Canon::<S>::encoded_len(&ROOT_TAG) + ident_len::<S>(),
+        }
+    }
+}
+
+// Manual implementation of `Canon`, storing the children of a branch on
+// their own and encoding their idents. Branch hashes are recomputed on read,
+// and branches whose split bits do not strictly increase, or do not split
+// the keys below them, are rejected.
+impl<S> Canon<S> for Node<S::Ident>
+where
+    S: Store,
+{
+    fn write(&self, sink: &mut impl Sink<S>) -> Result<(), S::Error> {
+        match self {
+            Node::Leaf { key, hash } => {
+                LEAF_TAG.write(sink)?;
+                key.write(sink)?;
+                write_ident::<S>(hash, sink);
+                Ok(())
+            }
+            Node::Branch {
+                split, left, right, ..
+            } => {
+                BRANCH_TAG.write(sink)?;
+                split.write(sink)?;
+                let left = sink.recur(&**left)?;
+                let right = sink.recur(&**right)?;
+                write_ident::<S>(&left, sink);
+                write_ident::<S>(&right, sink);
+                Ok(())
+            }
+        }
+    }
+
+    fn read(source: &mut impl Source<S>) -> Result<Self, S::Error> {
+        match u8::read(source)? {
+            LEAF_TAG => {
+                let key = ContractId::read(source)?;
+                let hash = read_ident::<S>(source);
+                Ok(Node::Leaf { key, hash })
+            }
+            BRANCH_TAG => {
+                let split = u16::read(source)?;
+                let left = read_ident::<S>(source);
+                let right = read_ident::<S>(source);
+
+                let left: Rc<Self> = Rc::new(source.store().get(&left)?);
+                let right: Rc<Self> = Rc::new(source.store().get(&right)?);
+
+                let below = |child: &Self| match child {
+                    Node::Branch { split: below, .. } => *below > split,
+                    Node::Leaf { .. } => true,
+                };
+
+                let (left_key, right_key) = (left.any_key(), right.any_key());
+                if split >= KEY_BITS
+                    || !below(&left)
+                    || !below(&right)
+                    || bit(left_key, split)
+                    || !bit(right_key, split)
+                    || first_difference(left_key, right_key) != Some(split)
+                {
+                    return Err(InvalidEncoding::invalid_encoding());
+                }
+
+                Ok(Node::Branch {
+                    split,
+                    hash: node(split, left.hash(), right.hash()),
+                    left,
+                    right,
+                })
+            }
+            _ => Err(InvalidEncoding::invalid_encoding()),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        match self {
+            Node::Leaf { key, .. } => {
+                Canon::<S>::encoded_len(&LEAF_TAG)
+                    + Canon::<S>::encoded_len(key)
+                    + ident_len::<S>()
+            }
+            Node::Branch { split, .. } => {
+                Canon::<S>::encoded_len(&BRANCH_TAG)
+                    + Canon::<S>::encoded_len(split)
+                    + 2 * ident_len::<S>()
+            }
+        }
+    }
+}
+
+/// Writes the bytes of an ident to `sink`
+pub(crate) fn write_ident<S: Store>(ident: &S::Ident, sink: &mut impl Sink<S>) {
+    sink.copy_bytes(ident.as_ref());
+}
+
+/// Reads the bytes of an ident from `source`
+pub(crate) fn read_ident<S: Store>(source: &mut impl Source<S>) -> S::Ident {
+    let mut ident = S::Ident::default();
+    let len = ident.as_ref().len();
+    ident.as_mut().copy_from_slice(source.read_bytes(len));
+    ident
+}
+
+/// Returns the length of the encoding of an ident
+pub(crate) fn ident_len<S: Store>() -> usize {
+    S::Ident::default().as_ref().len()
+}
+
+fn hash<I: Ident>(prefix: u8, parts: &[&[u8]]) -> I {
+    let mut bytes = vec![prefix];
+    for part in parts {
+        bytes.extend_from_slice(part);
+    }
+    I::from_bytes(&bytes)
+}
@@ -4,7 +4,7 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use super::AbiCall;
+use super::{slice_mut, AbiCall};
 use crate::call_context::CallContext;
 use crate::VMError;
 
@@ -25,13 +25,11 @@ impl<S: Store> AbiCall<S> for Callee {
             let result_ofs = result_ofs as usize;
             let callee = *context.callee();
 
-            context
-                .memory_mut(|a| {
-                    a[result_ofs..result_ofs + 32]
-                        .copy_from_slice(callee.as_bytes());
-                    Ok(None)
-                })
-                .map_err(VMError::from_store_error)
+            context.memory_mut(|a| {
+                slice_mut(a, result_ofs, 32)?
+                    .copy_from_slice(callee.as_bytes());
+                Ok(None)
+            })
         } else {
             Err(VMError::InvalidArguments)
         }
@@ -4,7 +4,7 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use super::AbiCall;
+use super::{slice, AbiCall};
 use crate::call_context::CallContext;
 use crate::VMError;
 
@@ -28,8 +28,7 @@ impl<S: Store> AbiCall<S> for Debug {
                 let msg_ofs = msg_ofs as usize;
                 let msg_len = msg_len as usize;
 
-                let slice = &a[msg_ofs..msg_ofs + msg_len];
-                let str = std::str::from_utf8(slice)
+                let str = std::str::from_utf8(slice(a, msg_ofs, msg_len)?)
                     .map_err(|_| VMError::InvalidUtf8)?;
                 println!("CONTRACT DEBUG: {:?}", str);
                 Ok(None)
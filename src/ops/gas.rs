@@ -21,11 +21,32 @@ impl<S: Store> AbiCall<S> for Gas {
         context: &mut CallContext<S>,
         args: RuntimeArgs,
     ) -> Result<Option<RuntimeValue>, VMError<S>> {
-        let meter = context.gas_meter_mut();
         let gas: u32 = args.nth_checked(0)?;
-        if meter.charge(gas as u64).is_out_of_gas() {
-            return Err(VMError::OutOfGas);
-        }
+        context.charge(gas as u64)?;
+        Ok(None)
+    }
+}
+
+pub struct ChargeGas;
+
+impl<S: Store> AbiCall<S> for ChargeGas {
+    const ARGUMENTS: &'static [ValueType] = &[ValueType::I32, ValueType::I32];
+    const RETURN: Option<ValueType> = None;
+
+    fn call(
+        context: &mut CallContext<S>,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, VMError<S>> {
+        let gas: u32 = args.nth_checked(0)?;
+        let function: i32 = args.nth_checked(1)?;
+
+        // negative indices stand for functions added by the instrumentation
+        context.enter_function(if function < 0 {
+            None
+        } else {
+            Some(function as u32)
+        });
+        context.charge(gas as u64)?;
         Ok(None)
     }
 }
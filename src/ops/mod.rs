@@ -8,7 +8,7 @@ use crate::call_context::CallContext;
 use crate::VMError;
 
 use canonical::Store;
-use wasmi::{RuntimeArgs, RuntimeValue, ValueType};
+use wasmi::{RuntimeArgs, RuntimeValue, TrapKind, ValueType};
 
 pub mod block_height;
 pub mod callee;
@@ -16,9 +16,81 @@ pub mod debug;
 pub mod gas;
 pub mod panic;
 pub mod query;
+pub mod reentrancy;
 pub mod store;
 pub mod transact;
 
+/// Status returned by gas-limited calls when the callee succeeded
+pub const CALL_OK: i32 = 0;
+/// Status returned by gas-limited calls when the callee ran out of gas
+pub const CALL_OUT_OF_GAS: i32 = 1;
+/// Status returned by gas-limited calls when the callee trapped or panicked
+pub const CALL_FAILED: i32 = 2;
+
+/// Returns the status of a gas-limited call that failed with `error`, if the
+/// failure belongs to the callee, or passes `error` up otherwise
+///
+/// Only running out of gas, trapping and panicking are left to the caller to
+/// recover from. Errors of the host, of the store, and breaches of the rules
+/// of the VM, such as re-entering a contract, mutating the state in a query
+/// or nesting calls too deeply, abort the whole call.
+pub fn call_status<S: Store>(error: VMError<S>) -> Result<i32, VMError<S>> {
+    match error {
+        VMError::OutOfGas => Ok(CALL_OUT_OF_GAS),
+        VMError::ContractPanic(_) | VMError::MemoryLimitExceeded => {
+            Ok(CALL_FAILED)
+        }
+        VMError::Trap(ref trap)
+        | VMError::WasmiError(wasmi::Error::Trap(ref trap))
+            if !matches!(
+                trap.kind(),
+                TrapKind::Host(_) | TrapKind::StackOverflow
+            ) =>
+        {
+            Ok(CALL_FAILED)
+        }
+        error => Err(error),
+    }
+}
+
+/// Returns the `len` bytes of `memory` at offset `ofs`, failing if they are
+/// not all within the memory
+pub fn slice<S: Store>(
+    memory: &[u8],
+    ofs: usize,
+    len: usize,
+) -> Result<&[u8], VMError<S>> {
+    ofs.checked_add(len)
+        .and_then(|end| memory.get(ofs..end))
+        .ok_or(VMError::MemoryAccessOutOfBounds)
+}
+
+/// Mutable version of [`slice`]
+pub fn slice_mut<S: Store>(
+    memory: &mut [u8],
+    ofs: usize,
+    len: usize,
+) -> Result<&mut [u8], VMError<S>> {
+    ofs.checked_add(len)
+        .and_then(move |end| memory.get_mut(ofs..end))
+        .ok_or(VMError::MemoryAccessOutOfBounds)
+}
+
+/// Returns the bytes of `memory` from offset `ofs` to its end
+pub fn tail<S: Store>(memory: &[u8], ofs: usize) -> Result<&[u8], VMError<S>> {
+    memory.get(ofs..).ok_or(VMError::MemoryAccessOutOfBounds)
+}
+
+/// Mutable version of [`tail`]
+pub fn tail_mut<S: Store>(
+    memory: &mut [u8],
+    ofs: usize,
+) -> Result<&mut [u8], VMError<S>> {
+    memory
+        .get_mut(ofs..)
+        .ok_or(VMError::MemoryAccessOutOfBounds)
+}
+
 pub trait AbiCall<S>
 where
     S: Store,
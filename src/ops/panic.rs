@@ -4,7 +4,7 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use super::AbiCall;
+use super::{slice, AbiCall};
 use crate::call_context::CallContext;
 use crate::VMError;
 
@@ -30,13 +30,13 @@ impl<S: Store> AbiCall<S> for Panic {
             context.memory(|a| {
                 Err(
                     match String::from_utf8(
-                        a[panic_ofs..panic_ofs + panic_len].to_vec(),
+                        slice(a, panic_ofs, panic_len)?.to_vec(),
                     ) {
                         Ok(panic_msg) => VMError::ContractPanic(panic_msg),
                         Err(_) => VMError::InvalidUtf8,
                     },
                 )
-            })?
+            })
         } else {
             Err(VMError::InvalidArguments)
         }
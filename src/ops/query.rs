@@ -5,11 +5,11 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use crate::call_context::CallContext;
-use crate::ops::AbiCall;
+use crate::ops::{call_status, slice, tail, tail_mut, AbiCall, CALL_OK};
 use crate::VMError;
 
 use canonical::{ByteSink, ByteSource, Canon, Store};
-use dusk_abi::ContractId;
+use dusk_abi::{ContractId, Query, ReturnValue};
 use wasmi::{RuntimeArgs, RuntimeValue, ValueType};
 
 pub struct ExecuteQuery;
@@ -28,36 +28,86 @@ impl<S: Store> AbiCall<S> for ExecuteQuery {
             let contract_id_ofs = contract_id_ofs as usize;
             let query_ofs = query_ofs as usize;
 
-            let (contract_id, query) = context
-                .memory(|m| {
-                    let contract_id = ContractId::from(
-                        &m[contract_id_ofs..contract_id_ofs + 32],
-                    );
+            let (contract_id, query) =
+                read_query(context, contract_id_ofs, query_ofs)?;
 
-                    let mut source =
-                        ByteSource::new(&m[query_ofs..], context.store());
+            let result = context.query(contract_id, query)?;
 
-                    let query = Canon::<S>::read(&mut source)?;
+            write_result(context, query_ofs, &result)?;
 
-                    Ok((contract_id, query))
-                })
-                .map_err(VMError::from_store_error)?;
+            Ok(None)
+        } else {
+            Err(VMError::InvalidArguments)
+        }
+    }
+}
 
-            let result = context.query(contract_id, query)?;
+/// Query with a gas limit, returning a status code instead of trapping when
+/// the callee fails.
+pub struct ExecuteQueryWithGas;
 
-            let store = context.store().clone();
+impl<S: Store> AbiCall<S> for ExecuteQueryWithGas {
+    const ARGUMENTS: &'static [ValueType] =
+        &[ValueType::I32, ValueType::I32, ValueType::I64];
+    const RETURN: Option<ValueType> = Some(ValueType::I32);
 
-            context
-                .memory_mut(|m| {
-                    // write back the return value
-                    let mut sink = ByteSink::new(&mut m[query_ofs..], &store);
-                    Canon::<S>::write(&result, &mut sink)
-                })
-                .map_err(VMError::from_store_error)?;
+    fn call(
+        context: &mut CallContext<S>,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, VMError<S>> {
+        if let [RuntimeValue::I32(contract_id_ofs), RuntimeValue::I32(query_ofs), RuntimeValue::I64(gas_limit)] =
+            *args.as_ref()
+        {
+            let contract_id_ofs = contract_id_ofs as usize;
+            let query_ofs = query_ofs as usize;
+            let gas_limit = gas_limit as u64;
 
-            Ok(None)
+            let (contract_id, query) =
+                read_query(context, contract_id_ofs, query_ofs)?;
+
+            let status =
+                match context.query_with_gas(contract_id, query, gas_limit) {
+                    Ok(result) => {
+                        write_result(context, query_ofs, &result)?;
+                        CALL_OK
+                    }
+                    Err(error) => call_status(error)?,
+                };
+
+            Ok(Some(RuntimeValue::I32(status)))
         } else {
             Err(VMError::InvalidArguments)
         }
     }
 }
+
+fn read_query<S: Store>(
+    context: &CallContext<S>,
+    contract_id_ofs: usize,
+    query_ofs: usize,
+) -> Result<(ContractId, Query), VMError<S>> {
+    context.memory(|m| {
+        let contract_id = ContractId::from(slice(m, contract_id_ofs, 32)?);
+
+        let mut source = ByteSource::new(tail(m, query_ofs)?, context.store());
+
+        let query =
+            Canon::<S>::read(&mut source).map_err(VMError::from_store_error)?;
+
+        Ok((contract_id, query))
+    })
+}
+
+fn write_result<S: Store>(
+    context: &mut CallContext<S>,
+    query_ofs: usize,
+    result: &ReturnValue,
+) -> Result<(), VMError<S>> {
+    let store = context.store().clone();
+
+    context.memory_mut(|m| {
+        // write back the return value
+        let mut sink = ByteSink::new(tail_mut(m, query_ofs)?, &store);
+        Canon::<S>::write(result, &mut sink).map_err(VMError::from_store_error)
+    })
+}
@@ -0,0 +1,30 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use super::AbiCall;
+use crate::call_context::CallContext;
+use crate::VMError;
+
+use canonical::Store;
+use wasmi::{RuntimeArgs, RuntimeValue, ValueType};
+
+/// Returns 1 if the calling contract has been re-entered, that is if it has
+/// another frame on the call stack, 0 otherwise.
+pub struct Reentered;
+
+impl<S: Store> AbiCall<S> for Reentered {
+    const ARGUMENTS: &'static [ValueType] = &[];
+    const RETURN: Option<ValueType> = Some(ValueType::I32);
+
+    fn call(
+        context: &mut CallContext<S>,
+        _args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, VMError<S>> {
+        let reentered = context.reentered();
+
+        Ok(Some(RuntimeValue::I32(reentered as i32)))
+    }
+}
@@ -4,8 +4,8 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use crate::call_context::CallContext;
-use crate::ops::AbiCall;
+use crate::call_context::{per_byte, CallContext};
+use crate::ops::{slice, slice_mut, AbiCall};
 use crate::VMError;
 
 use canonical::Store;
@@ -24,24 +24,56 @@ impl<S: Store> AbiCall<S> for Get {
         if let [RuntimeValue::I32(ofs)] = *args.as_ref() {
             let ofs = ofs as usize;
             let store = context.store().clone();
-            context
-                .memory_mut(|mem| {
-                    // read identifier
-                    let mut id = S::Ident::default();
-                    let id_len = id.as_ref().len();
-                    let slice = &mem[ofs..ofs + id_len];
-                    id.as_mut().copy_from_slice(slice);
 
-                    store.fetch(&id, &mut mem[ofs..])?;
-                    Ok(None)
-                })
-                .map_err(VMError::from_store_error)
+            let schedule = context.state().schedule();
+            let max_len = schedule.max_store_value_len as usize;
+            context.charge(schedule.store_base_cost)?;
+
+            // read identifier
+            let id = context.memory(|mem| {
+                let mut id = S::Ident::default();
+                let id_len = id.as_ref().len();
+                id.as_mut().copy_from_slice(slice(mem, ofs, id_len)?);
+                Ok::<_, VMError<S>>(id)
+            })?;
+
+            let len = value_len(&store, &id, max_len)?;
+
+            context.charge(per_byte(
+                context.state().schedule().sandbox_data_write_cost,
+                len,
+            ))?;
+
+            let value: Vec<u8> =
+                store.get(&id).map_err(VMError::from_store_error)?;
+
+            context.memory_mut(|mem| {
+                slice_mut(mem, ofs, value.len())?.copy_from_slice(&value);
+                Ok(None)
+            })
         } else {
             Err(VMError::InvalidArguments)
         }
     }
 }
 
+// Values are stored length prefixed, so the length of a value is decoded on
+// its own, to be checked against `max_len` and charged for before the value
+// itself is fetched.
+fn value_len<S: Store>(
+    store: &S,
+    id: &S::Ident,
+    max_len: usize,
+) -> Result<usize, VMError<S>> {
+    let len: u64 = store.get(id).map_err(VMError::from_store_error)?;
+
+    if len > max_len as u64 {
+        return Err(VMError::StoreValueTooLarge);
+    }
+
+    Ok(len as usize)
+}
+
 pub struct Put;
 
 impl<S: Store> AbiCall<S> for Put {
@@ -60,16 +92,23 @@ impl<S: Store> AbiCall<S> for Put {
             let len = len as usize;
             let ret = ret as usize;
             let store = context.store().clone();
-            context
-                .memory_mut(|mem| {
-                    if let Ok(id) = store.put_raw(&mem[ofs..ofs + len]) {
-                        let id_len = id.as_ref().len();
-                        // write id back
-                        mem[ret..ret + id_len].copy_from_slice(id.as_ref());
-                    }
-                    Ok(None)
-                })
-                .map_err(VMError::from_store_error)
+
+            let schedule = context.state().schedule();
+            let cost = schedule
+                .store_base_cost
+                .saturating_add(per_byte(schedule.sandbox_data_read_cost, len));
+            context.charge(cost)?;
+
+            context.memory_mut(|mem| {
+                // stored as a `Vec<u8>`, prefixing the value with its length
+                let value = slice(mem, ofs, len)?.to_vec();
+                if let Ok(id) = store.put(&value) {
+                    let id_len = id.as_ref().len();
+                    // write id back
+                    slice_mut(mem, ret, id_len)?.copy_from_slice(id.as_ref());
+                }
+                Ok(None)
+            })
         } else {
             Err(VMError::InvalidArguments)
         }
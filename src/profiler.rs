@@ -0,0 +1,169 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use parity_wasm::elements;
+
+use crate::contract::ContractId;
+use crate::gas::Gas;
+
+/// A call frame in a profile: a contract, and the function of the contract
+/// being executed, if known.
+pub type ProfileFrame = (ContractId, Option<u32>);
+
+/// Attributes the gas spent during contract execution to the contracts and
+/// wasm functions spending it, including their nested call frames.
+///
+/// A profiler is passed to [`NetworkState::query_profiled`] or
+/// [`NetworkState::transact_profiled`], and can be reused over several calls
+/// to accumulate their gas.
+///
+/// Only the contracts on the call stack are tracked, not the wasm calls
+/// within a contract: the gas spent in a contract frame is attributed to the
+/// function currently executing, as its only function, with no distinction
+/// between the chains of wasm calls leading to it. Profiles hold one frame
+/// per contract call, and the gas of a function includes none of the
+/// functions it calls within the same contract.
+///
+/// [`NetworkState::query_profiled`]: crate::NetworkState::query_profiled
+/// [`NetworkState::transact_profiled`]: crate::NetworkState::transact_profiled
+#[derive(Debug, Default)]
+pub struct GasProfiler {
+    // gas spent by the innermost frame of each call stack
+    samples: HashMap<Vec<ProfileFrame>, Gas>,
+    // function names from the wasm name section of each contract
+    names: HashMap<ContractId, HashMap<u32, String>>,
+}
+
+impl GasProfiler {
+    /// Creates a new, empty, `GasProfiler`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the total gas recorded
+    pub fn total(&self) -> Gas {
+        self.samples.values().sum()
+    }
+
+    /// Returns the gas spent by each function itself, excluding the calls it
+    /// made, sorted from the most to the least expensive
+    ///
+    /// The gas charged by the host before entering the first contract, such as
+    /// the cost of the outermost call, is not attributed to any function.
+    pub fn functions(&self) -> Vec<(ProfileFrame, Gas)> {
+        let mut functions = HashMap::new();
+
+        for (stack, gas) in &self.samples {
+            if let Some(frame) = stack.last() {
+                *functions.entry(*frame).or_insert(0) += gas;
+            }
+        }
+
+        let mut functions: Vec<_> = functions.into_iter().collect();
+        functions.sort_by(|(a, a_gas), (b, b_gas)| {
+            b_gas
+                .cmp(a_gas)
+                .then_with(|| self.frame_name(a).cmp(&self.frame_name(b)))
+        });
+        functions
+    }
+
+    /// Returns the name of a frame, as `contract::function`
+    ///
+    /// The contract is abbreviated to the hex encoding of its first four
+    /// bytes, and functions missing from the name section are named after
+    /// their index.
+    pub fn frame_name(&self, frame: &ProfileFrame) -> String {
+        let (contract, function) = frame;
+
+        let contract_name: String = contract.as_bytes()[..4]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        let function_name = match function {
+            Some(index) => self
+                .names
+                .get(contract)
+                .and_then(|names| names.get(index))
+                .cloned()
+                .unwrap_or_else(|| format!("func[{}]", index)),
+            None => "[vm]".into(),
+        };
+
+        format!("{}::{}", contract_name, function_name)
+    }
+
+    /// Returns the profile in the folded stacks format, one line per call
+    /// stack, as consumed by flamegraph tools
+    ///
+    /// The gas charged outside of any contract is attributed to `[host]`.
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<_> = self
+            .samples
+            .iter()
+            .map(|(stack, gas)| {
+                let frames: Vec<_> =
+                    stack.iter().map(|frame| self.frame_name(frame)).collect();
+                if frames.is_empty() {
+                    format!("[host] {}", gas)
+                } else {
+                    format!("{} {}", frames.join(";"), gas)
+                }
+            })
+            .collect();
+
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    pub(crate) fn record<I>(&mut self, stack: I, gas: Gas)
+    where
+        I: IntoIterator<Item = ProfileFrame>,
+    {
+        *self.samples.entry(stack.into_iter().collect()).or_insert(0) += gas;
+    }
+
+    pub(crate) fn load_names(&mut self, contract: ContractId, bytecode: &[u8]) {
+        if self.names.contains_key(&contract) {
+            return;
+        }
+
+        let names =
+            parity_wasm::deserialize_buffer::<elements::Module>(bytecode)
+                .map(|module| {
+                    module.parse_names().unwrap_or_else(|(_, module)| module)
+                })
+                .ok()
+                .and_then(|module| {
+                    module
+                        .names_section()
+                        .and_then(|section| section.functions())
+                        .map(|functions| {
+                            functions
+                                .names()
+                                .iter()
+                                .map(|(index, name)| (index, name.clone()))
+                                .collect()
+                        })
+                })
+                .unwrap_or_default();
+
+        self.names.insert(contract, names);
+    }
+}
+
+impl fmt::Display for GasProfiler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (frame, gas) in self.functions() {
+            writeln!(f, "{:>12} {}", gas, self.frame_name(&frame))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use canonical::{Canon, Ident, InvalidEncoding, Sink, Source, Store};
+use dusk_abi::{ContractId, ContractState};
+
+use crate::merkle::{self, Metadata, KEY_BITS};
+
+// Tags of the encoded terminals of a proof
+const PRESENT_TAG: u8 = 0;
+const ABSENT_TAG: u8 = 1;
+const EMPTY_TAG: u8 = 2;
+
+/// Proof that a contract is part of the state with a given root, or that it
+/// is absent from it, checked with [`verify_proof`]
+///
+/// Returned by [`NetworkState::prove`], and encoded with `Canon` to be sent
+/// to light clients.
+///
+/// [`NetworkState::prove`]: crate::NetworkState::prove
+#[derive(Debug, Clone)]
+pub struct ContractProof<I> {
+    metadata: Metadata<I>,
+    // the branches passed by the walk for the contract in the contracts
+    // trie, as their split bit and the hash of the child not taken
+    path: Vec<(u16, I)>,
+    terminal: Terminal<I>,
+}
+
+// The leaf the walk for the contract ends at
+#[derive(Debug, Clone)]
+enum Terminal<I> {
+    Present { code_hash: I, state: ContractState },
+    // the contract the walk ends at instead, if the trie is not empty
+    Absent(Option<Neighbor<I>>),
+}
+
+#[derive(Debug, Clone)]
+struct Neighbor<I> {
+    id: ContractId,
+    code_hash: I,
+    state_hash: I,
+}
+
+impl<I: Ident> ContractProof<I> {
+    /// Builds the proof that a contract with the given code hash and state,
+    /// whose walk passes `path`, is part of the state
+    pub(crate) fn present(
+        metadata: Metadata<I>,
+        path: Vec<(u16, I)>,
+        code_hash: I,
+        state: ContractState,
+    ) -> Self {
+        ContractProof {
+            metadata,
+            path,
+            terminal: Terminal::Present { code_hash, state },
+        }
+    }
+
+    /// Builds the proof that a contract whose walk passes `path` and ends at
+    /// the leaf of `neighbor`, given as its id, code hash and state hash, is
+    /// absent from the state
+    pub(crate) fn absent(
+        metadata: Metadata<I>,
+        path: Vec<(u16, I)>,
+        neighbor: Option<(ContractId, I, I)>,
+    ) -> Self {
+        let neighbor = neighbor.map(|(id, code_hash, state_hash)| Neighbor {
+            id,
+            code_hash,
+            state_hash,
+        });
+
+        ContractProof {
+            metadata,
+            path,
+            terminal: Terminal::Absent(neighbor),
+        }
+    }
+
+    /// Returns the hash of the bytecode and the state of the contract, or
+    /// `None` if the proof is one of absence
+    pub fn contract(&self) -> Option<(&I, &ContractState)> {
+        match &self.terminal {
+            Terminal::Present { code_hash, state } => Some((code_hash, state)),
+            Terminal::Absent(_) => None,
+        }
+    }
+}
+
+// Manual implementation of `Canon`, since the idents of a store are not
+// `Canon` themselves. Paths longer than the bits of a key are rejected.
+impl<S> Canon<S> for ContractProof<S::Ident>
+where
+    S: Store,
+{
+    fn write(&self, sink: &mut impl Sink<S>) -> Result<(), S::Error> {
+        self.metadata.write(sink)?;
+
+        (self.path.len() as u16).write(sink)?;
+        for (split, sibling) in self.path.iter() {
+            split.write(sink)?;
+            merkle::write_ident::<S>(sibling, sink);
+        }
+
+        match &self.terminal {
+            Terminal::Present { code_hash, state } => {
+                PRESENT_TAG.write(sink)?;
+                merkle::write_ident::<S>(code_hash, sink);
+                state.write(sink)
+            }
+            Terminal::Absent(Some(neighbor)) => {
+                ABSENT_TAG.write(sink)?;
+                neighbor.id.write(sink)?;
+                merkle::write_ident::<S>(&neighbor.code_hash, sink);
+                merkle::write_ident::<S>(&neighbor.state_hash, sink);
+                Ok(())
+            }
+            Terminal::Absent(None) => EMPTY_TAG.write(sink),
+        }
+    }
+
+    fn read(source: &mut impl Source<S>) -> Result<Self, S::Error> {
+        let metadata = Metadata::read(source)?;
+
+        let len = u16::read(source)?;
+        if len > KEY_BITS {
+            return Err(InvalidEncoding::invalid_encoding());
+        }
+
+        let mut path = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            let split = u16::read(source)?;
+            path.push((split, merkle::read_ident::<S>(source)));
+        }
+
+        let terminal = match u8::read(source)? {
+            PRESENT_TAG => Terminal::Present {
+                code_hash: merkle::read_ident::<S>(source),
+                state: ContractState::read(source)?,
+            },
+            ABSENT_TAG => Terminal::Absent(Some(Neighbor {
+                id: ContractId::read(source)?,
+                code_hash: merkle::read_ident::<S>(source),
+                state_hash: merkle::read_ident::<S>(source),
+            })),
+            EMPTY_TAG => Terminal::Absent(None),
+            _ => return Err(InvalidEncoding::invalid_encoding()),
+        };
+
+        Ok(ContractProof {
+            metadata,
+            path,
+            terminal,
+        })
+    }
+
+    fn encoded_len(&self) -> usize {
+        let path = Canon::<S>::encoded_len(&(self.path.len() as u16))
+            + self.path.len()
+                * (Canon::<S>::encoded_len(&0u16) + merkle::ident_len::<S>());
+
+        let terminal = Canon::<S>::encoded_len(&PRESENT_TAG)
+            + match &self.terminal {
+                Terminal::Present { state, .. } => {
+                    merkle::ident_len::<S>() + Canon::<S>::encoded_len(state)
+                }
+                Terminal::Absent(Some(neighbor)) => {
+                    Canon::<S>::encoded_len(&neighbor.id)
+                        + 2 * merkle::ident_len::<S>()
+                }
+                Terminal::Absent(None) => 0,
+            };
+
+        Canon::<S>::encoded_len(&self.metadata) + path + terminal
+    }
+}
+
+/// Checks that `proof` shows the contract at `contract_id` to be part of the
+/// state with the given `root`, with the code hash and state returned by
+/// [`ContractProof::contract`], or to be absent from it
+///
+/// Returns false if the proof is for another contract or another state.
+pub fn verify_proof<I: Ident>(
+    root: &I,
+    contract_id: &ContractId,
+    proof: &ContractProof<I>,
+) -> bool {
+    let path = &proof.path;
+
+    // the walk for a contract present in the trie ends at its own leaf, so
+    // ending at the leaf of another one shows it is absent
+    let contracts_root = match &proof.terminal {
+        Terminal::Present { code_hash, state } => {
+            let state_hash = I::from_bytes(state.as_bytes());
+            let leaf =
+                merkle::contract_leaf(contract_id, code_hash, &state_hash);
+            merkle::root_from_path(contract_id, leaf, path)
+        }
+        Terminal::Absent(Some(neighbor)) if neighbor.id != *contract_id => {
+            let leaf = merkle::contract_leaf(
+                &neighbor.id,
+                &neighbor.code_hash,
+                &neighbor.state_hash,
+            );
+            merkle::root_from_path(contract_id, leaf, path)
+        }
+        Terminal::Absent(Some(_)) => None,
+        Terminal::Absent(None) if path.is_empty() => Some(I::default()),
+        Terminal::Absent(None) => None,
+    };
+
+    match contracts_root {
+        Some(contracts_root) => proof.metadata.root(&contracts_root) == *root,
+        None => false,
+    }
+}
@@ -0,0 +1,65 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use canonical::{Canon, InvalidEncoding, Sink, Source, Store};
+
+/// Policy applied when a contract is called while it already has a frame on
+/// the call stack
+///
+/// The frames of a re-entered contract below the new one keep working on
+/// the state the contract had when they were entered, so re-entrant calls
+/// may observe inconsistent views of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReentrancyPolicy {
+    /// Contracts can never be re-entered
+    Forbid,
+    /// Contracts can only be re-entered by calling themselves directly
+    SelfCalls,
+    /// Contracts can be re-entered by any call
+    Allow,
+}
+
+impl Default for ReentrancyPolicy {
+    fn default() -> Self {
+        ReentrancyPolicy::Allow
+    }
+}
+
+// Manual implementation of `Canon`, encoding the policy as a single byte.
+// Unknown bytes are rejected as an invalid encoding.
+impl<S> Canon<S> for ReentrancyPolicy
+where
+    S: Store,
+{
+    fn write(&self, sink: &mut impl Sink<S>) -> Result<(), S::Error> {
+        (*self as u8).write(sink)
+    }
+
+    fn read(source: &mut impl Source<S>) -> Result<Self, S::Error> {
+        match u8::read(source)? {
+            0 => Ok(ReentrancyPolicy::Forbid),
+            1 => Ok(ReentrancyPolicy::SelfCalls),
+            2 => Ok(ReentrancyPolicy::Allow),
+            _ => Err(InvalidEncoding::invalid_encoding()),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        Canon::<S>::encoded_len(&(*self as u8))
+    }
+}
+
+impl ReentrancyPolicy {
+    /// Returns true if a contract with a frame on the call stack can be
+    /// called, `self_call` being set if it is calling itself directly
+    pub(crate) fn permits(self, self_call: bool) -> bool {
+        match self {
+            ReentrancyPolicy::Forbid => false,
+            ReentrancyPolicy::SelfCalls => self_call,
+            ReentrancyPolicy::Allow => true,
+        }
+    }
+}
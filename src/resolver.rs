@@ -43,7 +43,32 @@ macro_rules! abi_resolver {
 
                     ,
 
-                    _ => panic!("invalid function name {:?}", field_name)
+                    _ => Err(wasmi::Error::Instantiation(
+                        format!("invalid function name {:?}", field_name),
+                    )),
+                }
+            }
+        }
+
+        impl<$s: Store> $name<$s> {
+            /// Returns the index and the signature of the function imported
+            /// as `field_name`, if any
+            pub(crate) fn import(field_name: &str) -> Option<(usize, Signature)>
+            where $(
+                $op : AbiCall<$s>,
+                )*
+            {
+                match field_name {
+                    $(
+                        $op_name => Some(($id, Signature::new(
+                            <$op as AbiCall<$s>>::ARGUMENTS,
+                            <$op as AbiCall<$s>>::RETURN,
+                        )))
+                    ),*
+
+                    ,
+
+                    _ => None,
                 }
             }
         }
@@ -80,6 +105,10 @@ abi_resolver! {
         7, "transact" => transact::ApplyTransaction,
         9, "callee" => callee::Callee,
         10, "gas" => gas::Gas,
-        11, "block_height" => block_height::BlockHeight
+        11, "block_height" => block_height::BlockHeight,
+        12, "query_gas" => query::ExecuteQueryWithGas,
+        13, "transact_gas" => transact::ApplyTransactionWithGas,
+        14, "charge_gas" => gas::ChargeGas,
+        15, "reentered" => reentrancy::Reentered
     }
 }
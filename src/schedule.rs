@@ -0,0 +1,207 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use canonical::{Canon, Sink, Source, Store};
+use canonical_derive::Canon;
+
+use crate::gas::Gas;
+
+/// Definition of the cost schedule and other parameterizations for wasm vm.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Clone, PartialEq, Eq, Hash, Canon)]
+pub struct Schedule {
+    /// Version of the schedule.
+    pub version: u32,
+
+    /// Cost of putting a byte of code into storage.
+    pub put_code_per_byte_cost: Gas,
+
+    /// Gas cost of a growing memory by single page.
+    pub grow_mem_cost: Gas,
+
+    /// Gas cost of a regular operation.
+    pub regular_op_cost: Gas,
+
+    /// Gas cost per one byte returned.
+    pub return_data_per_byte_cost: Gas,
+
+    /// Gas cost to deposit an event; the per-byte portion.
+    pub event_data_per_byte_cost: Gas,
+
+    /// Gas cost to deposit an event; the cost per topic.
+    pub event_per_topic_cost: Gas,
+
+    /// Gas cost to deposit an event; the base.
+    pub event_base_cost: Gas,
+
+    /// Base gas cost to call into a contract.
+    pub call_base_cost: Gas,
+
+    /// Base gas cost to instantiate a contract.
+    pub instantiate_base_cost: Gas,
+
+    /// Base gas cost of getting or putting a value in the store.
+    pub store_base_cost: Gas,
+
+    /// Maximum length of a value a contract can get from the store, in
+    /// bytes.
+    pub max_store_value_len: u32,
+
+    /// Gas cost per one byte read from the sandbox memory.
+    pub sandbox_data_read_cost: Gas,
+
+    /// Gas cost per one byte written to the sandbox memory.
+    pub sandbox_data_write_cost: Gas,
+
+    /// The maximum number of topics supported by an event.
+    pub max_event_topics: u32,
+
+    /// Maximum allowed stack height.
+    ///
+    /// See https://wiki.parity.io/WebAssembly-StackHeight to find out
+    /// how the stack frame cost is calculated.
+    pub max_stack_height: u32,
+
+    /// Maximum number of contract calls that can be nested, including the
+    /// outermost one.
+    pub max_call_depth: u32,
+
+    /// Maximum number of memory pages allowed for a contract.
+    ///
+    /// Contracts compiled from Rust reserve 1 MiB, or 16 pages, for their
+    /// stack before their data, so the limit leaves room for their data and
+    /// for the call buffer appended to their memory on every call.
+    pub max_memory_pages: u32,
+
+    /// Maximum allowed size of a declared table.
+    pub max_table_size: u32,
+
+    /// Maximum size of a contract bytecode, in bytes.
+    pub max_code_size: u32,
+
+    /// Whether contracts may use floating-point instructions and types, in
+    /// which case the NaNs they produce are canonicalized. Otherwise
+    /// contracts using them are rejected.
+    pub allow_floats: bool,
+
+    /// Whether the `ext_println` function is allowed to be used contracts.
+    /// MUST only be enabled for `dev` chains, NOT for production chains
+    pub enable_println: bool,
+
+    /// The maximum length of a subject used for PRNG generation.
+    pub max_subject_len: u32,
+}
+
+impl Default for Schedule {
+    fn default() -> Schedule {
+        Schedule {
+            version: 0,
+            put_code_per_byte_cost: 1,
+            grow_mem_cost: 1,
+            regular_op_cost: 1,
+            return_data_per_byte_cost: 1,
+            event_data_per_byte_cost: 1,
+            event_per_topic_cost: 1,
+            event_base_cost: 1,
+            call_base_cost: 135,
+            instantiate_base_cost: 175,
+            store_base_cost: 100,
+            max_store_value_len: 64 * 1024,
+            sandbox_data_read_cost: 1,
+            sandbox_data_write_cost: 1,
+            max_event_topics: 4,
+            max_stack_height: 64 * 1024,
+            max_call_depth: 64,
+            max_memory_pages: 64,
+            max_table_size: 16 * 1024,
+            max_code_size: 1024 * 1024,
+            allow_floats: false,
+            enable_println: false,
+            max_subject_len: 32,
+        }
+    }
+}
+
+impl Schedule {
+    /// Returns a hash of all the parameters of the schedule, telling apart
+    /// schedules that share a version but differ otherwise
+    pub(crate) fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// Table of [`Schedule`]s, indexed by the block height they apply from.
+///
+/// The table always holds a schedule activated at height 0.
+#[derive(Clone)]
+pub struct ScheduleTable {
+    entries: Vec<(u64, Schedule)>,
+}
+
+// Manual implementation of `Canon`, rebuilding the table on read so that it
+// is sorted and holds a schedule at height 0 whatever the encoded entries.
+impl<S> Canon<S> for ScheduleTable
+where
+    S: Store,
+{
+    fn write(&self, sink: &mut impl Sink<S>) -> Result<(), S::Error> {
+        self.entries.write(sink)
+    }
+
+    fn read(source: &mut impl Source<S>) -> Result<Self, S::Error> {
+        let entries: Vec<(u64, Schedule)> = Canon::<S>::read(source)?;
+
+        let mut table = ScheduleTable::default();
+        for (activation_height, schedule) in entries {
+            table.insert(activation_height, schedule);
+        }
+        Ok(table)
+    }
+
+    fn encoded_len(&self) -> usize {
+        Canon::<S>::encoded_len(&self.entries)
+    }
+}
+
+impl Default for ScheduleTable {
+    fn default() -> Self {
+        ScheduleTable {
+            entries: vec![(0, Schedule::default())],
+        }
+    }
+}
+
+impl ScheduleTable {
+    /// Activates `schedule` from `activation_height` onwards, replacing any
+    /// schedule previously activated at the same height.
+    pub fn insert(&mut self, activation_height: u64, schedule: Schedule) {
+        match self
+            .entries
+            .binary_search_by_key(&activation_height, |(height, _)| *height)
+        {
+            Ok(i) => self.entries[i].1 = schedule,
+            Err(i) => self.entries.insert(i, (activation_height, schedule)),
+        }
+    }
+
+    /// Returns the schedule in force at `block_height`
+    pub fn at(&self, block_height: u64) -> &Schedule {
+        let i = match self
+            .entries
+            .binary_search_by_key(&block_height, |(height, _)| *height)
+        {
+            Ok(i) => i,
+            // the entry at height 0 is never removed, so `i` is at least 1
+            Err(i) => i - 1,
+        };
+        &self.entries[i].1
+    }
+}
@@ -6,57 +6,194 @@
 
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
 
-use canonical::{ByteSource, Canon, Ident, Sink, Source, Store};
-use dusk_abi::{HostModule, Query, Transaction};
+use canonical::{
+    ByteSink, ByteSource, Canon, Ident, InvalidEncoding, Sink, Source, Store,
+};
+use dusk_abi::{ContractState, HostModule, Query, ReturnValue, Transaction};
 use dusk_kelvin_map::Map;
 
+use crate::block::Block;
+use crate::cache::{CacheStats, ModuleCache};
 use crate::call_context::CallContext;
 use crate::contract::{Contract, ContractId};
-use crate::gas::GasMeter;
+use crate::disk_store::RootStore;
+use crate::engine::{EngineKind, Module};
+use crate::fee::{Fee, Receipt};
+use crate::gas::{Gas, GasMeter};
+use crate::instrument;
+use crate::merkle::{self, Metadata, Trie};
+use crate::profiler::GasProfiler;
+use crate::proof::ContractProof;
+use crate::reentrancy::ReentrancyPolicy;
+use crate::schedule::{Schedule, ScheduleTable};
 use crate::VMError;
 
 type BoxedHostModule<S> = Box<dyn HostModule<S>>;
 
 /// The main network state, includes the full state of contracts.
-#[derive(Clone, Default)]
 pub struct NetworkState<S>
 where
     S: Store,
 {
     block_height: u64,
     contracts: Map<ContractId, Contract, S>,
+    balances: Map<ContractId, u64, S>,
+    // tries over the leaves of `contracts` and `balances`, updated along
+    // with them, whose roots the state root commits to
+    contract_trie: Trie<S::Ident>,
+    balance_trie: Trie<S::Ident>,
     modules: Rc<RefCell<HashMap<ContractId, BoxedHostModule<S>>>>,
+    module_cache: Rc<RefCell<ModuleCache<S>>>,
+    schedules: ScheduleTable,
+    // hash of the encoding of `schedules`, updated along with them
+    schedules_hash: S::Ident,
+    reentrancy: ReentrancyPolicy,
+    engine: EngineKind,
+    // shared with the forks of the state, and copied by its clones
+    checkpoints: Rc<RefCell<Checkpoints<S>>>,
     store: S,
 }
 
+/// Handle to a checkpoint of a [`NetworkState`], returned by
+/// [`NetworkState::checkpoint`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Checkpoint(u64);
+
+// The checkpoints of a state, in the order they were taken, with their ids.
+#[derive(Clone)]
+struct Checkpoints<S>
+where
+    S: Store,
+{
+    taken: Vec<(u64, Snapshot<S>)>,
+    next: u64,
+}
+
+impl<S> Default for Checkpoints<S>
+where
+    S: Store,
+{
+    fn default() -> Self {
+        Checkpoints {
+            taken: vec![],
+            next: 0,
+        }
+    }
+}
+
+impl<S> Checkpoints<S>
+where
+    S: Store,
+{
+    fn index(&self, checkpoint: &Checkpoint) -> Result<usize, VMError<S>> {
+        self.taken
+            .iter()
+            .position(|(id, _)| *id == checkpoint.0)
+            .ok_or(VMError::UnknownCheckpoint)
+    }
+}
+
+// The parts of a state a checkpoint restores, which are the ones it commits
+// to. The maps and tries share their nodes with the state.
+#[derive(Clone)]
+struct Snapshot<S>
+where
+    S: Store,
+{
+    block_height: u64,
+    contracts: Map<ContractId, Contract, S>,
+    balances: Map<ContractId, u64, S>,
+    contract_trie: Trie<S::Ident>,
+    balance_trie: Trie<S::Ident>,
+    schedules: ScheduleTable,
+    schedules_hash: S::Ident,
+    reentrancy: ReentrancyPolicy,
+}
+
+// Clones of the state get their own copy of the checkpoints, so that
+// checkpoints taken, reverted to or released on a clone do not affect the
+// original.
+impl<S> Clone for NetworkState<S>
+where
+    S: Store,
+{
+    fn clone(&self) -> Self {
+        let checkpoints = self.checkpoints.borrow().clone();
+
+        NetworkState {
+            checkpoints: Rc::new(RefCell::new(checkpoints)),
+            ..self.fork()
+        }
+    }
+}
+
 // Manual implementation of `Canon` to ignore the "modules" which needs to be
-// re-instantiated on program initialization.
+// re-instantiated on program initialization, the "module_cache" whose
+// modules are re-created lazily on first use, the "engine" and the
+// "checkpoints" which are local to the node, and the "schedules_hash" which
+// is recomputed on read. The tries are checked against the maps on read.
 impl<S> Canon<S> for NetworkState<S>
 where
     S: Store,
 {
     fn write(&self, sink: &mut impl Sink<S>) -> Result<(), S::Error> {
         self.block_height.write(sink)?;
-        self.contracts.write(sink)
+        self.contracts.write(sink)?;
+        self.balances.write(sink)?;
+        self.schedules.write(sink)?;
+        self.reentrancy.write(sink)?;
+        self.contract_trie.write(sink)?;
+        self.balance_trie.write(sink)
     }
 
     fn read(source: &mut impl Source<S>) -> Result<Self, S::Error> {
         let block_height = u64::read(source)?;
         let contracts = Map::read(source)?;
+        let balances = Map::read(source)?;
+        let schedules = ScheduleTable::read(source)?;
+        let reentrancy = ReentrancyPolicy::read(source)?;
+        let contract_trie = Trie::read(source)?;
+        let balance_trie = Trie::read(source)?;
+        check_tries(&contracts, &balances, &contract_trie, &balance_trie)?;
+        let schedules_hash = schedules_hash(&schedules, source.store())?;
         Ok(NetworkState {
             block_height,
             contracts,
+            balances,
+            contract_trie,
+            balance_trie,
+            schedules,
+            schedules_hash,
+            reentrancy,
+            engine: EngineKind::default(),
+            checkpoints: Rc::new(RefCell::new(Checkpoints::default())),
             store: source.store().clone(),
             modules: Rc::new(RefCell::new(HashMap::new())),
+            module_cache: Rc::new(RefCell::new(ModuleCache::default())),
         })
     }
 
     fn encoded_len(&self) -> usize {
         Canon::<S>::encoded_len(&self.block_height)
             + Canon::<S>::encoded_len(&self.contracts)
+            + Canon::<S>::encoded_len(&self.balances)
+            + Canon::<S>::encoded_len(&self.schedules)
+            + Canon::<S>::encoded_len(&self.reentrancy)
+            + Canon::<S>::encoded_len(&self.contract_trie)
+            + Canon::<S>::encoded_len(&self.balance_trie)
+    }
+}
+
+impl<S> Default for NetworkState<S>
+where
+    S: Store,
+{
+    fn default() -> Self {
+        Self::with_block_height(0)
     }
 }
 
@@ -66,26 +203,316 @@ where
 {
     /// Returns a [`NetworkState`] for a specific block height
     pub fn with_block_height(block_height: u64) -> Self {
+        let store = S::default();
+        let schedules = ScheduleTable::default();
+        let schedules_hash = schedules_hash(&schedules, &store)
+            .expect("schedules are plain data");
+
         Self {
             block_height,
             contracts: Map::default(),
+            balances: Map::default(),
+            contract_trie: Trie::default(),
+            balance_trie: Trie::default(),
             modules: Rc::new(RefCell::new(HashMap::new())),
-            store: S::default(),
+            module_cache: Rc::new(RefCell::new(ModuleCache::default())),
+            schedules,
+            schedules_hash,
+            reentrancy: ReentrancyPolicy::default(),
+            engine: EngineKind::default(),
+            checkpoints: Rc::new(RefCell::new(Checkpoints::default())),
+            store,
+        }
+    }
+
+    /// Returns an empty [`NetworkState`] backed by `store`, which it is
+    /// committed to
+    pub fn with_store(store: S) -> Self {
+        Self {
+            store,
+            ..Self::default()
+        }
+    }
+
+    /// Writes the state to its store, returning the ident it can be loaded
+    /// back from with [`NetworkState::load`]
+    ///
+    /// The root of the store is left as it is, see
+    /// [`NetworkState::commit_root`] to also update it. Host modules and the
+    /// engine are not part of the committed state, and must be set again
+    /// after loading it.
+    pub fn commit(&self) -> Result<S::Ident, VMError<S>> {
+        self.store.put(self).map_err(VMError::from_store_error)
+    }
+
+    /// Loads the state committed to `store` under `root`
+    pub fn load(store: S, root: &S::Ident) -> Result<Self, VMError<S>> {
+        store.get(root).map_err(VMError::from_store_error)
+    }
+
+    /// Writes the state to its store and makes it the root of the store, for
+    /// it to be restored with [`NetworkState::restore`] after a restart
+    ///
+    /// The root is only replaced once the state is durable, so a store
+    /// always restores the last state committed to it in full.
+    pub fn commit_root(&self) -> Result<S::Ident, VMError<S>>
+    where
+        S: RootStore,
+    {
+        let root = self.commit()?;
+        self.store
+            .set_root(&root)
+            .map_err(VMError::from_store_error)?;
+        Ok(root)
+    }
+
+    /// Loads the state committed last to `store` with
+    /// [`NetworkState::commit_root`], or returns `None` if there is none
+    pub fn restore(store: S) -> Result<Option<Self>, VMError<S>>
+    where
+        S: RootStore,
+    {
+        match store.root() {
+            Some(root) => Self::load(store, &root).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Returns a copy of the state sharing its checkpoints, to replace it
+    // once done with, such as the fork a block or a transaction runs on.
+    pub(crate) fn fork(&self) -> Self {
+        NetworkState {
+            block_height: self.block_height,
+            contracts: self.contracts.clone(),
+            balances: self.balances.clone(),
+            contract_trie: self.contract_trie.clone(),
+            balance_trie: self.balance_trie.clone(),
+            modules: self.modules.clone(),
+            module_cache: self.module_cache.clone(),
+            schedules: self.schedules.clone(),
+            schedules_hash: self.schedules_hash,
+            reentrancy: self.reentrancy,
+            engine: self.engine,
+            checkpoints: self.checkpoints.clone(),
+            store: self.store.clone(),
+        }
+    }
+
+    /// Saves the current state, for it to be restored with
+    /// [`NetworkState::revert_to`] until the returned handle is released
+    ///
+    /// Checkpoints are cheap to take, the maps and tries of the state being
+    /// persistent. They are copied along with the state when it is cloned,
+    /// and are local to the node, not being committed.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        let mut checkpoints = self.checkpoints.borrow_mut();
+        let id = checkpoints.next;
+        checkpoints.next += 1;
+
+        let snapshot = Snapshot {
+            block_height: self.block_height,
+            contracts: self.contracts.clone(),
+            balances: self.balances.clone(),
+            contract_trie: self.contract_trie.clone(),
+            balance_trie: self.balance_trie.clone(),
+            schedules: self.schedules.clone(),
+            schedules_hash: self.schedules_hash,
+            reentrancy: self.reentrancy,
+        };
+        checkpoints.taken.push((id, snapshot));
+
+        Checkpoint(id)
+    }
+
+    /// Restores the state saved by `checkpoint`, releasing the checkpoints
+    /// taken after it
+    ///
+    /// Fails with [`VMError::UnknownCheckpoint`] if the checkpoint has been
+    /// released.
+    pub fn revert_to(
+        &mut self,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), VMError<S>> {
+        let snapshot = {
+            let mut checkpoints = self.checkpoints.borrow_mut();
+            let index = checkpoints.index(checkpoint)?;
+            checkpoints.taken.truncate(index + 1);
+            checkpoints.taken[index].1.clone()
+        };
+
+        self.block_height = snapshot.block_height;
+        self.contracts = snapshot.contracts;
+        self.balances = snapshot.balances;
+        self.contract_trie = snapshot.contract_trie;
+        self.balance_trie = snapshot.balance_trie;
+        self.schedules = snapshot.schedules;
+        self.schedules_hash = snapshot.schedules_hash;
+        self.reentrancy = snapshot.reentrancy;
+
+        Ok(())
+    }
+
+    /// Releases `checkpoint`, keeping the state as it is
+    ///
+    /// Fails with [`VMError::UnknownCheckpoint`] if the checkpoint has been
+    /// released already, by reverting to an earlier one.
+    pub fn release(
+        &mut self,
+        checkpoint: Checkpoint,
+    ) -> Result<(), VMError<S>> {
+        let mut checkpoints = self.checkpoints.borrow_mut();
+        let index = checkpoints.index(&checkpoint)?;
+        checkpoints.taken.remove(index);
+        Ok(())
+    }
+
+    /// Returns the root hash of the state, committing to the code and state
+    /// of every contract, the balances, the block height, the schedules and
+    /// the reentrancy policy
+    ///
+    /// Contracts and balances are each the leaves of a binary trie keyed by
+    /// id, whose shape only depends on the ids it holds, so the root only
+    /// depends on the content of the state and not on the history that led
+    /// to it. The tries are updated along with the state, and computing the
+    /// root does not traverse them.
+    pub fn root(&self) -> Result<S::Ident, VMError<S>> {
+        Ok(self.metadata().root(&self.contract_trie.root()))
+    }
+
+    /// Returns a proof that the contract at `contract_id` is part of the
+    /// state, along with its code hash and state, or that it is absent from
+    /// it, to be checked against [`NetworkState::root`] with
+    /// [`verify_proof`]
+    ///
+    /// [`verify_proof`]: crate::verify_proof
+    pub fn prove(
+        &self,
+        contract_id: &ContractId,
+    ) -> Result<ContractProof<S::Ident>, VMError<S>> {
+        let (path, reached) = self.contract_trie.path(contract_id);
+
+        // the walk for a contract ends at its own leaf if it is present
+        Ok(match reached {
+            Some(id) if id == *contract_id => {
+                let contract = self.get_contract(contract_id)?;
+                ContractProof::present(
+                    self.metadata(),
+                    path,
+                    S::Ident::from_bytes(contract.bytecode()),
+                    contract.state().clone(),
+                )
+            }
+            Some(id) => {
+                let contract = self.get_contract(&id)?;
+                ContractProof::absent(
+                    self.metadata(),
+                    path,
+                    Some((
+                        id,
+                        S::Ident::from_bytes(contract.bytecode()),
+                        S::Ident::from_bytes(contract.state().as_bytes()),
+                    )),
+                )
+            }
+            None => ContractProof::absent(self.metadata(), path, None),
+        })
+    }
+
+    // Returns everything the root commits to besides the contracts.
+    fn metadata(&self) -> Metadata<S::Ident> {
+        Metadata {
+            block_height: self.block_height,
+            balances_root: self.balance_trie.root(),
+            schedules_hash: self.schedules_hash,
+            reentrancy: self.reentrancy as u8,
         }
     }
 
     /// Deploys a contract to the state, returns the address of the created
     /// contract or an error
+    ///
+    /// The contract bytecode is instrumented with gas metering according to
+    /// the [`Schedule`] in force, failing if the bytecode is not a valid
+    /// module.
     pub fn deploy(
         &mut self,
         contract: Contract,
-    ) -> Result<ContractId, S::Error> {
+    ) -> Result<ContractId, VMError<S>> {
         let id: ContractId = S::Ident::from_bytes(contract.bytecode()).into();
 
-        self.contracts.insert(id, contract)?;
+        let module = catch_panic(|| self.compile(contract.bytecode()))?;
+        self.module_cache
+            .borrow_mut()
+            .insert((id, self.schedule().fingerprint(), self.engine), module);
+
+        let leaf = contract_leaf::<S>(&id, &contract);
+        self.contracts
+            .insert(id, contract)
+            .map_err(VMError::from_store_error)?;
+        self.contract_trie = self.contract_trie.insert(&id, leaf);
         Ok(id)
     }
 
+    /// Returns the gas-metered module of the specified contract, instrumenting
+    /// the contract bytecode if it is not cached already
+    pub(crate) fn instrumented_module(
+        &self,
+        contract_id: &ContractId,
+    ) -> Result<Rc<dyn Module<S>>, VMError<S>> {
+        self.module_cache.borrow_mut().get_or_try_insert(
+            (*contract_id, self.schedule().fingerprint(), self.engine),
+            || {
+                let contract = self.get_contract(contract_id)?;
+                self.compile(contract.bytecode())
+            },
+        )
+    }
+
+    // Instruments `bytecode` according to the schedule in force, and compiles
+    // it with the engine of the state.
+    fn compile(
+        &self,
+        bytecode: &[u8],
+    ) -> Result<Rc<dyn Module<S>>, VMError<S>> {
+        let module = instrument::instrument(bytecode, self.schedule())?;
+        self.engine.engine::<S>().compile(module)
+    }
+
+    /// Returns the kind of engine contracts are executed with
+    pub fn engine(&self) -> EngineKind {
+        self.engine
+    }
+
+    /// Executes contracts with the engine of the given `kind` from now on
+    ///
+    /// All engines produce the same results and spend the same gas. Defaults
+    /// to [`EngineKind::Wasmi`].
+    pub fn set_engine(&mut self, kind: EngineKind) {
+        self.engine = kind;
+    }
+
+    /// Returns the hit and miss counters of the module cache
+    ///
+    /// The cache is shared by all the clones of a state.
+    pub fn module_cache_stats(&self) -> CacheStats {
+        self.module_cache.borrow().stats()
+    }
+
+    /// Returns the number of modules in the module cache
+    pub fn cached_modules(&self) -> usize {
+        self.module_cache.borrow().len()
+    }
+
+    /// Bounds the number of modules kept in the module cache, evicting the
+    /// least recently used ones beyond `capacity`
+    ///
+    /// Defaults to [`DEFAULT_CACHE_CAPACITY`].
+    ///
+    /// [`DEFAULT_CACHE_CAPACITY`]: crate::DEFAULT_CACHE_CAPACITY
+    pub fn set_module_cache_capacity(&self, capacity: usize) {
+        self.module_cache.borrow_mut().set_capacity(capacity);
+    }
+
     /// Returns a reference to the specified contracts state
     pub fn get_contract<'a>(
         &'a self,
@@ -98,16 +525,25 @@ where
             .unwrap_or(Err(VMError::UnknownContract))
     }
 
-    /// Returns a reference to the specified contracts state
-    pub fn get_contract_mut<'a>(
-        &'a mut self,
+    /// Replaces the state of the specified contract
+    pub fn set_contract_state(
+        &mut self,
         contract_id: &ContractId,
-    ) -> Result<impl DerefMut<Target = Contract> + 'a, VMError<S>> {
-        self.contracts
-            .get_mut(contract_id)
-            .map_err(VMError::from_store_error)
-            .transpose()
-            .unwrap_or(Err(VMError::UnknownContract))
+        state: ContractState,
+    ) -> Result<(), VMError<S>> {
+        let leaf = {
+            let mut contract = self
+                .contracts
+                .get_mut(contract_id)
+                .map_err(VMError::from_store_error)?
+                .ok_or(VMError::UnknownContract)?;
+
+            *contract.state_mut() = state;
+            contract_leaf::<S>(contract_id, &contract)
+        };
+
+        self.contract_trie = self.contract_trie.insert(contract_id, leaf);
+        Ok(())
     }
 
     /// Returns a reference to the map of registered host modules
@@ -122,30 +558,121 @@ where
         &self.store
     }
 
+    /// Returns the [`Schedule`] used to price and limit contract execution at
+    /// the state's block height
+    pub fn schedule(&self) -> &Schedule {
+        self.schedules.at(self.block_height)
+    }
+
+    /// Returns the [`Schedule`] in force at the given block height
+    pub fn schedule_at(&self, block_height: u64) -> &Schedule {
+        self.schedules.at(block_height)
+    }
+
+    /// Activates a [`Schedule`] from `activation_height` onwards, replacing
+    /// the one previously activated at the same height, if any
+    ///
+    /// Contracts are instrumented again for every distinct schedule, even
+    /// one sharing the `version` of another.
+    pub fn add_schedule(&mut self, activation_height: u64, schedule: Schedule) {
+        self.schedules.insert(activation_height, schedule);
+        self.schedules_hash = schedules_hash(&self.schedules, &self.store)
+            .expect("schedules are plain data");
+    }
+
+    /// Returns the policy applied to calls re-entering a contract
+    pub fn reentrancy_policy(&self) -> ReentrancyPolicy {
+        self.reentrancy
+    }
+
+    /// Sets the policy applied to calls re-entering a contract, whose
+    /// violations fail with [`VMError::Reentrancy`]
+    ///
+    /// Defaults to [`ReentrancyPolicy::Allow`].
+    pub fn set_reentrancy_policy(&mut self, policy: ReentrancyPolicy) {
+        self.reentrancy = policy;
+    }
+
     /// Returns the state's block height
     pub fn block_height(&self) -> u64 {
         self.block_height
     }
 
-    /// Queryn the contract at address `target`
-    pub fn query<A, R>(
+    /// Starts executing a block at `block_height`, which must be above the
+    /// height of the state, failing with [`VMError::InvalidBlockHeight`]
+    /// otherwise
+    ///
+    /// The state is only changed once the returned [`Block`] is finalized.
+    pub fn begin_block(
         &mut self,
+        block_height: u64,
+    ) -> Result<Block<S>, VMError<S>> {
+        if block_height <= self.block_height {
+            return Err(VMError::InvalidBlockHeight(block_height));
+        }
+
+        let mut fork = self.fork();
+        fork.block_height = block_height;
+
+        Ok(Block::new(self, fork))
+    }
+
+    /// Query the contract at address `target`
+    ///
+    /// Queries cannot mutate the state, contracts trying to transact while
+    /// being queried fail with [`VMError::MutationInQuery`].
+    pub fn query<A, R>(
+        &self,
+        target: ContractId,
+        query: A,
+        gas_meter: &mut GasMeter,
+    ) -> Result<R, VMError<S>>
+    where
+        A: Canon<S>,
+        R: Canon<S>,
+    {
+        self.query_inner(target, query, gas_meter, None)
+    }
+
+    /// Query the contract at address `target`, attributing the gas spent to
+    /// the contracts and functions spending it in `profiler`
+    pub fn query_profiled<A, R>(
+        &self,
         target: ContractId,
         query: A,
         gas_meter: &mut GasMeter,
+        profiler: &mut GasProfiler,
+    ) -> Result<R, VMError<S>>
+    where
+        A: Canon<S>,
+        R: Canon<S>,
+    {
+        self.query_inner(target, query, gas_meter, Some(profiler))
+    }
+
+    fn query_inner<A, R>(
+        &self,
+        target: ContractId,
+        query: A,
+        gas_meter: &mut GasMeter,
+        profiler: Option<&mut GasProfiler>,
     ) -> Result<R, VMError<S>>
     where
         A: Canon<S>,
         R: Canon<S>,
     {
         let store = self.store().clone();
-        let mut context = CallContext::new(self, gas_meter, &store)?;
 
-        let result = context.query(
-            target,
-            Query::from_canon(&query, &store)
-                .map_err(VMError::from_store_error)?,
-        )?;
+        // Queries are not allowed to change the state, which is only borrowed
+        let mut context = CallContext::new_query(self, gas_meter, &store)?;
+
+        if let Some(profiler) = profiler {
+            context.profile_with(profiler);
+        }
+
+        let query = Query::from_canon(&query, &store)
+            .map_err(VMError::from_store_error)?;
+        let result = catch_panic(|| context.query(target, query))?;
 
         result.cast(store).map_err(VMError::from_store_error)
     }
@@ -160,22 +687,77 @@ where
     where
         A: Canon<S>,
         R: Canon<S>,
+    {
+        self.transact_inner(target, transaction, gas_meter, None)
+    }
+
+    /// Transact with the contract at address `target`, attributing the gas
+    /// spent to the contracts and functions spending it in `profiler`
+    pub fn transact_profiled<A, R>(
+        &mut self,
+        target: ContractId,
+        transaction: A,
+        gas_meter: &mut GasMeter,
+        profiler: &mut GasProfiler,
+    ) -> Result<R, VMError<S>>
+    where
+        A: Canon<S>,
+        R: Canon<S>,
+    {
+        self.transact_inner(target, transaction, gas_meter, Some(profiler))
+    }
+
+    fn transact_inner<A, R>(
+        &mut self,
+        target: ContractId,
+        transaction: A,
+        gas_meter: &mut GasMeter,
+        profiler: Option<&mut GasProfiler>,
+    ) -> Result<R, VMError<S>>
+    where
+        A: Canon<S>,
+        R: Canon<S>,
+    {
+        let store = self.store().clone();
+
+        let transaction = Transaction::from_canon(&transaction, &store)
+            .map_err(VMError::from_store_error)?;
+
+        self.transact_raw(target, transaction, gas_meter, profiler, |result| {
+            result.cast(store).map_err(VMError::from_store_error)
+        })
+    }
+
+    // Executes an encoded `transaction` against a fork of the state, which
+    // replaces the state if the transaction succeeds and `finish` accepts its
+    // return value.
+    pub(crate) fn transact_raw<T, F>(
+        &mut self,
+        target: ContractId,
+        transaction: Transaction,
+        gas_meter: &mut GasMeter,
+        profiler: Option<&mut GasProfiler>,
+        finish: F,
+    ) -> Result<T, VMError<S>>
+    where
+        F: FnOnce(ReturnValue) -> Result<T, VMError<S>>,
     {
         let store = self.store().clone();
 
         // Fork the current network's state
-        let mut fork = self.clone();
+        let mut fork = self.fork();
 
         // Use the forked state to execute the transaction
         let mut context = CallContext::new(&mut fork, gas_meter, &store)?;
 
-        let (_, result) = context.transact(
-            target,
-            Transaction::from_canon(&transaction, &store)
-                .map_err(VMError::from_store_error)?,
-        )?;
+        if let Some(profiler) = profiler {
+            context.profile_with(profiler);
+        }
+
+        let (_, result) =
+            catch_panic(|| context.transact(target, transaction))?;
 
-        let ret = result.cast(store).map_err(VMError::from_store_error)?;
+        let ret = finish(result)?;
 
         // If we reach this point, everything went well and we can use the
         // updates made in the forked state.
@@ -184,6 +766,186 @@ where
         Ok(ret)
     }
 
+    /// Transact with the contract at address `target`, paying for the gas
+    /// spent from the balance of `payer`
+    ///
+    /// The maximum fee allowed by `fee` is reserved from the payer up front,
+    /// failing with [`VMError::NotEnoughFunds`] if the balance does not cover
+    /// it. The gas left unused is refunded once the transaction is executed.
+    /// The fee is charged even if the transaction fails, in which case the
+    /// failure is reported in the returned [`Receipt`].
+    pub fn transact_with_fee<A, R>(
+        &mut self,
+        payer: ContractId,
+        target: ContractId,
+        transaction: A,
+        fee: Fee,
+    ) -> Result<Receipt<R, S>, VMError<S>>
+    where
+        A: Canon<S>,
+        R: Canon<S>,
+    {
+        self.with_fee(payer, fee, |state, gas_meter| {
+            state.transact(target, transaction, gas_meter)
+        })
+    }
+
+    // Runs `transact` with the gas limit of `fee`, settling the fee for the
+    // gas it spent with `payer`, as described in `transact_with_fee`.
+    pub(crate) fn with_fee<R, F>(
+        &mut self,
+        payer: ContractId,
+        fee: Fee,
+        transact: F,
+    ) -> Result<Receipt<R, S>, VMError<S>>
+    where
+        F: FnOnce(&mut Self, &mut GasMeter) -> Result<R, VMError<S>>,
+    {
+        let reserved = fee.max_cost().ok_or(VMError::NotEnoughFunds)?;
+        let balance = self.balance(&payer)?;
+
+        if balance < reserved {
+            return Err(VMError::NotEnoughFunds);
+        }
+
+        self.set_balance(payer, balance - reserved)?;
+
+        let mut gas_meter = GasMeter::with_limit(fee.gas_limit());
+        let result = transact(self, &mut gas_meter);
+
+        // The spent gas never exceeds the limit, so neither does its cost.
+        let gas_spent = gas_meter.spent();
+        let charged = fee.cost(gas_spent).unwrap_or(reserved);
+
+        let balance = self.balance(&payer)?;
+        self.set_balance(payer, balance + (reserved - charged))?;
+
+        Ok(Receipt::new(gas_spent, charged, result))
+    }
+
+    /// Returns the balance of `account`, or zero if it has none
+    pub fn balance(&self, account: &ContractId) -> Result<u64, VMError<S>> {
+        Ok(self
+            .balances
+            .get(account)
+            .map_err(VMError::from_store_error)?
+            .map(|balance| *balance)
+            .unwrap_or(0))
+    }
+
+    /// Credits `amount` to the balance of `account`
+    pub fn deposit(
+        &mut self,
+        account: ContractId,
+        amount: u64,
+    ) -> Result<(), VMError<S>> {
+        let balance = self.balance(&account)?;
+        self.set_balance(account, balance.saturating_add(amount))
+    }
+
+    fn set_balance(
+        &mut self,
+        account: ContractId,
+        balance: u64,
+    ) -> Result<(), VMError<S>> {
+        self.balances
+            .insert(account, balance)
+            .map_err(VMError::from_store_error)?;
+        self.balance_trie = self
+            .balance_trie
+            .insert(&account, merkle::balance_leaf(&account, balance));
+        Ok(())
+    }
+
+    /// Estimates the gas needed to query the contract at address `target`
+    ///
+    /// The query is dry-run against the state with at most `gas_limit` gas,
+    /// returning the minimal limit with which it succeeds.
+    pub fn estimate_gas_query<A>(
+        &self,
+        target: ContractId,
+        query: A,
+        gas_limit: Gas,
+    ) -> Result<Gas, VMError<S>>
+    where
+        A: Canon<S>,
+    {
+        let query = Query::from_canon(&query, &self.store)
+            .map_err(VMError::from_store_error)?;
+
+        self.estimate_gas(gas_limit, |gas_meter| {
+            let store = self.store().clone();
+            let mut context = CallContext::new_query(self, gas_meter, &store)?;
+            catch_panic(|| context.query(target, query.clone())).map(|_| ())
+        })
+    }
+
+    /// Estimates the gas needed to transact with the contract at address
+    /// `target`
+    ///
+    /// The transaction is dry-run on a fork of the state with at most
+    /// `gas_limit` gas, returning the minimal limit with which it succeeds.
+    /// The state itself is left untouched.
+    pub fn estimate_gas_transact<A>(
+        &self,
+        target: ContractId,
+        transaction: A,
+        gas_limit: Gas,
+    ) -> Result<Gas, VMError<S>>
+    where
+        A: Canon<S>,
+    {
+        let transaction = Transaction::from_canon(&transaction, &self.store)
+            .map_err(VMError::from_store_error)?;
+
+        self.estimate_gas(gas_limit, |gas_meter| {
+            let store = self.store().clone();
+            let mut fork = self.fork();
+            let mut context = CallContext::new(&mut fork, gas_meter, &store)?;
+            catch_panic(|| context.transact(target, transaction.clone()))
+                .map(|_| ())
+        })
+    }
+
+    // Dry-runs `call` with gas limits up to `gas_limit`, searching for the
+    // minimal one with which it succeeds.
+    fn estimate_gas<C>(
+        &self,
+        gas_limit: Gas,
+        call: C,
+    ) -> Result<Gas, VMError<S>>
+    where
+        C: Fn(&mut GasMeter) -> Result<(), VMError<S>>,
+    {
+        let dry_run = |gas_limit: Gas| {
+            let mut gas_meter = GasMeter::with_limit(gas_limit);
+            call(&mut gas_meter).map(|_| gas_meter.spent())
+        };
+
+        let spent = dry_run(gas_limit)?;
+
+        // Gas-limited nested calls are given at most the gas left, so a call
+        // may need a higher limit than what it spends. The minimal limit
+        // lies between the two.
+        if dry_run(spent).is_ok() {
+            return Ok(spent);
+        }
+
+        let (mut failing, mut succeeding) = (spent, gas_limit);
+
+        while succeeding - failing > 1 {
+            let limit = failing + (succeeding - failing) / 2;
+
+            if dry_run(limit).is_ok() {
+                succeeding = limit;
+            } else {
+                failing = limit;
+            }
+        }
+
+        Ok(succeeding)
+    }
+
     /// Register a host-fn handler
     pub fn register_host_module<M>(&mut self, module: M)
     where
@@ -214,3 +976,69 @@ where
             })
     }
 }
+
+// Runs `f`, turning a panic of the host into an error, so that a hostile
+// contract can never bring the node down.
+fn catch_panic<R, S, F>(f: F) -> Result<R, VMError<S>>
+where
+    S: Store,
+    F: FnOnce() -> Result<R, VMError<S>>,
+{
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+
+        Err(VMError::HostPanic(message))
+    })
+}
+
+// Returns the leaf of the contract at `id` in the contracts trie.
+fn contract_leaf<S: Store>(id: &ContractId, contract: &Contract) -> S::Ident {
+    merkle::contract_leaf(
+        id,
+        &S::Ident::from_bytes(contract.bytecode()),
+        &S::Ident::from_bytes(contract.state().as_bytes()),
+    )
+}
+
+// Checks that the leaf of every id in the tries is the one of the contract
+// or the balance the maps hold for it, so that the root of a loaded state
+// commits to its content. The maps cannot be iterated over, so they are
+// checked from the ids of the tries.
+fn check_tries<S: Store>(
+    contracts: &Map<ContractId, Contract, S>,
+    balances: &Map<ContractId, u64, S>,
+    contract_trie: &Trie<S::Ident>,
+    balance_trie: &Trie<S::Ident>,
+) -> Result<(), S::Error> {
+    for (id, leaf) in contract_trie.leaves() {
+        match contracts.get(&id)? {
+            Some(contract) if contract_leaf::<S>(&id, &contract) == leaf => (),
+            _ => return Err(InvalidEncoding::invalid_encoding()),
+        }
+    }
+
+    for (id, leaf) in balance_trie.leaves() {
+        match balances.get(&id)? {
+            Some(balance) if merkle::balance_leaf(&id, *balance) == leaf => (),
+            _ => return Err(InvalidEncoding::invalid_encoding()),
+        }
+    }
+
+    Ok(())
+}
+
+// Returns the hash of the encoding of `schedules`, which the state root
+// commits to.
+fn schedules_hash<S: Store>(
+    schedules: &ScheduleTable,
+    store: &S,
+) -> Result<S::Ident, S::Error> {
+    let mut bytes = vec![0; Canon::<S>::encoded_len(schedules)];
+    let mut sink = ByteSink::new(&mut bytes[..], store);
+    schedules.write(&mut sink)?;
+    Ok(S::Ident::from_bytes(&bytes))
+}
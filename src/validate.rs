@@ -0,0 +1,324 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt;
+
+use canonical::Store;
+use parity_wasm::elements::{self, External, Internal, Type, ValueType};
+
+use crate::determinism;
+use crate::instrument::CHARGE_GAS_IMPORT;
+use crate::resolver::CompoundResolver;
+use crate::{Schedule, VMError};
+
+/// Import modules resolved by the VM
+const IMPORT_MODULES: [&str; 2] = ["env", "canon"];
+
+/// Exported entrypoints of a contract, both of which are required
+const ENTRYPOINTS: [&str; 2] = ["q", "t"];
+
+/// Prefixes of the exports added by the instrumentation, which contracts may
+/// not export themselves
+const RESERVED_EXPORT_PREFIXES: [&str; 2] =
+    ["__stack_height", "__memory_limit"];
+
+/// The reason a contract module was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidModule {
+    /// The bytecode is not a wasm module
+    Malformed,
+    /// The module failed wasm validation, with the validator message
+    Validation(String),
+    /// The bytecode is larger than `Schedule::max_code_size`
+    CodeTooLarge {
+        /// Size of the bytecode
+        size: usize,
+        /// Maximum size allowed
+        max: u32,
+    },
+    /// The module declares more memory than `Schedule::max_memory_pages`
+    TooManyMemoryPages {
+        /// Number of pages initially declared
+        initial: u32,
+        /// Maximum number of pages allowed
+        max: u32,
+    },
+    /// The module declares a table larger than `Schedule::max_table_size`
+    TableTooLarge {
+        /// Number of elements initially declared
+        initial: u32,
+        /// Maximum number of elements allowed
+        max: u32,
+    },
+    /// The module has a start function
+    StartFunction,
+    /// The module does not export a required item
+    MissingExport(&'static str),
+    /// The module exports an entrypoint with the wrong kind or signature
+    InvalidExport(String),
+    /// The module exports a name reserved to the instrumentation
+    ReservedExport(String),
+    /// The module imports an item the VM does not provide
+    UnknownImport {
+        /// Module of the import
+        module: String,
+        /// Name of the import
+        field: String,
+    },
+    /// The module imports a VM function with the wrong signature
+    ImportSignature {
+        /// Module of the import
+        module: String,
+        /// Name of the import
+        field: String,
+    },
+    /// The module could not be instrumented
+    Instrumentation,
+}
+
+impl fmt::Display for InvalidModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidModule::Malformed => write!(f, "malformed module"),
+            InvalidModule::Validation(e) => write!(f, "validation: {}", e),
+            InvalidModule::CodeTooLarge { size, max } => {
+                write!(f, "code size {} exceeds {} bytes", size, max)
+            }
+            InvalidModule::TooManyMemoryPages { initial, max } => {
+                write!(f, "{} memory pages exceed {} pages", initial, max)
+            }
+            InvalidModule::TableTooLarge { initial, max } => {
+                write!(f, "table size {} exceeds {}", initial, max)
+            }
+            InvalidModule::StartFunction => write!(f, "start function"),
+            InvalidModule::MissingExport(name) => {
+                write!(f, "missing export {:?}", name)
+            }
+            InvalidModule::InvalidExport(name) => {
+                write!(f, "invalid export {:?}", name)
+            }
+            InvalidModule::ReservedExport(name) => {
+                write!(f, "reserved export {:?}", name)
+            }
+            InvalidModule::UnknownImport { module, field } => {
+                write!(f, "unknown import {}::{}", module, field)
+            }
+            InvalidModule::ImportSignature { module, field } => {
+                write!(f, "invalid signature of import {}::{}", module, field)
+            }
+            InvalidModule::Instrumentation => write!(f, "instrumentation"),
+        }
+    }
+}
+
+/// Checks that the contract `bytecode` can be executed by the VM under the
+/// `schedule`, before it is instrumented.
+///
+/// The module must be valid wasm within the size limits of the schedule,
+/// must have no start function, must export its `memory` and both the `q`
+/// and `t` entrypoints, and none of the names reserved to the
+/// instrumentation, and may only import the functions of the
+/// [`CompoundResolver`], with matching signatures. Unless the schedule allows
+/// them, floating-point instructions are rejected.
+pub fn validate<S: Store>(
+    bytecode: &[u8],
+    schedule: &Schedule,
+) -> Result<elements::Module, VMError<S>> {
+    if bytecode.len() > schedule.max_code_size as usize {
+        return Err(VMError::InvalidWASMModule(InvalidModule::CodeTooLarge {
+            size: bytecode.len(),
+            max: schedule.max_code_size,
+        }));
+    }
+
+    let module: elements::Module = parity_wasm::deserialize_buffer(bytecode)
+        .map_err(|_| VMError::InvalidWASMModule(InvalidModule::Malformed))?;
+
+    check::<S>(&module, schedule).map_err(VMError::InvalidWASMModule)?;
+
+    if !schedule.allow_floats {
+        determinism::check_floats(&module)?;
+    }
+
+    Ok(module)
+}
+
+fn check<S: Store>(
+    module: &elements::Module,
+    schedule: &Schedule,
+) -> Result<(), InvalidModule> {
+    wasmi_validation::validate_module::<wasmi_validation::PlainValidator>(
+        module,
+    )
+    .map_err(|e| InvalidModule::Validation(e.to_string()))?;
+
+    if module.start_section().is_some() {
+        return Err(InvalidModule::StartFunction);
+    }
+
+    if let Some(section) = module.memory_section() {
+        for entry in section.entries() {
+            let initial = entry.limits().initial();
+            if initial > schedule.max_memory_pages {
+                return Err(InvalidModule::TooManyMemoryPages {
+                    initial,
+                    max: schedule.max_memory_pages,
+                });
+            }
+        }
+    }
+
+    if let Some(section) = module.table_section() {
+        for entry in section.entries() {
+            let initial = entry.limits().initial();
+            if initial > schedule.max_table_size {
+                return Err(InvalidModule::TableTooLarge {
+                    initial,
+                    max: schedule.max_table_size,
+                });
+            }
+        }
+    }
+
+    check_imports::<S>(module)?;
+    check_exports(module)
+}
+
+fn check_imports<S: Store>(
+    module: &elements::Module,
+) -> Result<(), InvalidModule> {
+    let entries = module
+        .import_section()
+        .map(|section| section.entries())
+        .unwrap_or(&[]);
+
+    for entry in entries {
+        let unknown = || InvalidModule::UnknownImport {
+            module: entry.module().into(),
+            field: entry.field().into(),
+        };
+
+        // the gas charging import is reserved to the instrumentation
+        if !IMPORT_MODULES.contains(&entry.module())
+            || entry.field() == CHARGE_GAS_IMPORT
+        {
+            return Err(unknown());
+        }
+
+        let type_index = match entry.external() {
+            External::Function(type_index) => *type_index,
+            _ => return Err(unknown()),
+        };
+
+        let (_, expected) =
+            CompoundResolver::<S>::import(entry.field()).ok_or_else(unknown)?;
+
+        let params: Vec<_> =
+            expected.params().iter().cloned().map(value_type).collect();
+        let ret = expected.return_type().map(value_type);
+
+        match function_type(module, type_index) {
+            Some(ty)
+                if ty.params() == params.as_slice()
+                    && ty.return_type() == ret => {}
+            _ => {
+                return Err(InvalidModule::ImportSignature {
+                    module: entry.module().into(),
+                    field: entry.field().into(),
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_exports(module: &elements::Module) -> Result<(), InvalidModule> {
+    let entries = module
+        .export_section()
+        .map(|section| section.entries())
+        .unwrap_or(&[]);
+
+    let export = |name| entries.iter().find(|entry| entry.field() == name);
+
+    if let Some(entry) = entries.iter().find(|entry| {
+        RESERVED_EXPORT_PREFIXES
+            .iter()
+            .any(|prefix| entry.field().starts_with(prefix))
+    }) {
+        return Err(InvalidModule::ReservedExport(entry.field().into()));
+    }
+
+    match export("memory").map(|entry| entry.internal()) {
+        Some(Internal::Memory(_)) => (),
+        Some(_) => return Err(InvalidModule::InvalidExport("memory".into())),
+        None => return Err(InvalidModule::MissingExport("memory")),
+    }
+
+    for name in ENTRYPOINTS.iter() {
+        let index = match export(name).map(|entry| entry.internal()) {
+            Some(Internal::Function(index)) => *index,
+            Some(_) => {
+                return Err(InvalidModule::InvalidExport(name.to_string()))
+            }
+            None => return Err(InvalidModule::MissingExport(name)),
+        };
+
+        // entrypoints take the offset of their argument in memory
+        match function_index_type(module, index)
+            .and_then(|type_index| function_type(module, type_index))
+        {
+            Some(ty)
+                if ty.params() == [ValueType::I32]
+                    && ty.return_type().is_none() => {}
+            _ => return Err(InvalidModule::InvalidExport(name.to_string())),
+        }
+    }
+
+    Ok(())
+}
+
+// Returns the type index of the function at `index` in the function index
+// space, imports included.
+fn function_index_type(module: &elements::Module, index: u32) -> Option<u32> {
+    let imported = module
+        .import_section()
+        .map(|section| section.entries())
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|entry| match entry.external() {
+            External::Function(type_index) => Some(*type_index),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    match imported.get(index as usize) {
+        Some(type_index) => Some(*type_index),
+        None => module
+            .function_section()?
+            .entries()
+            .get(index as usize - imported.len())
+            .map(|func| func.type_ref()),
+    }
+}
+
+fn function_type(
+    module: &elements::Module,
+    type_index: u32,
+) -> Option<&elements::FunctionType> {
+    match module.type_section()?.types().get(type_index as usize)? {
+        Type::Function(ty) => Some(ty),
+    }
+}
+
+fn value_type(ty: wasmi::ValueType) -> ValueType {
+    match ty {
+        wasmi::ValueType::I32 => ValueType::I32,
+        wasmi::ValueType::I64 => ValueType::I64,
+        wasmi::ValueType::F32 => ValueType::F32,
+        wasmi::ValueType::F64 => ValueType::F64,
+    }
+}
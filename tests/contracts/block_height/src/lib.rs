@@ -28,11 +28,10 @@ mod hosted {
 
     use super::*;
 
-    use canonical::{BridgeStore, ByteSink, ByteSource, Canon, Id32, Store};
+    use call_buffer::{buffer, write_output, HEADER_LEN};
+    use canonical::{BridgeStore, ByteSource, Canon, Id32, Store};
     use dusk_abi::ReturnValue;
 
-    const PAGE_SIZE: usize = 1024 * 4;
-
     type BS = BridgeStore<Id32>;
 
     impl BlockHeight {
@@ -41,9 +40,9 @@ mod hosted {
         }
     }
 
-    fn query(bytes: &mut [u8; PAGE_SIZE]) -> Result<(), <BS as Store>::Error> {
+    fn query(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(&bytes[..], &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let slf: BlockHeight = Canon::<BS>::read(&mut source)?;
@@ -54,24 +53,24 @@ mod hosted {
             BLOCK_HEIGHT => {
                 let ret = slf.block_height();
 
-                let r = {
-                    // return value
-                    let wrapped_return = ReturnValue::from_canon(&ret, &bs)?;
-
-                    let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                    Canon::<BS>::write(&wrapped_return, &mut sink)
-                };
+                // return value
+                let wrapped_return = ReturnValue::from_canon(&ret, &bs)?;
 
-                r
+                write_output(bytes, None, &wrapped_return, &bs)
             }
             _ => panic!(""),
         }
     }
 
     #[no_mangle]
-    fn q(bytes: &mut [u8; PAGE_SIZE]) {
+    fn q(ptr: *mut u8) {
         // todo, handle errors here
-        let _ = query(bytes);
+        let _ = query(buffer(ptr));
+    }
+
+    #[no_mangle]
+    fn t(_: *mut u8) {
+        // the contract has no transactions
+        panic!("")
     }
 }
@@ -0,0 +1,43 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Access to the call buffer the VM passes to the test contracts.
+
+#![no_std]
+
+use canonical::{ByteSink, Canon, Store};
+use dusk_abi::{ContractState, ReturnValue};
+
+/// The call buffer starts with the lengths of the two values following it
+pub const HEADER_LEN: usize = 8;
+
+/// Returns the call buffer at `ptr`, spanning up to the end of the memory
+#[cfg(target_arch = "wasm32")]
+pub fn buffer<'a>(ptr: *mut u8) -> &'a mut [u8] {
+    let memory_len = core::arch::wasm32::memory_size(0) * 64 * 1024;
+    unsafe { core::slice::from_raw_parts_mut(ptr, memory_len - ptr as usize) }
+}
+
+/// Writes the new `state`, if any, and the return value `ret` to the call
+/// buffer, after the header holding their lengths
+pub fn write_output<S: Store>(
+    bytes: &mut [u8],
+    state: Option<&ContractState>,
+    ret: &ReturnValue,
+    store: &S,
+) -> Result<(), S::Error> {
+    let state_len = state.map(Canon::<S>::encoded_len).unwrap_or(0);
+    let ret_len = Canon::<S>::encoded_len(ret);
+
+    bytes[..4].copy_from_slice(&(state_len as u32).to_le_bytes());
+    bytes[4..HEADER_LEN].copy_from_slice(&(ret_len as u32).to_le_bytes());
+
+    let mut sink = ByteSink::new(&mut bytes[HEADER_LEN..], store);
+    if let Some(state) = state {
+        Canon::<S>::write(state, &mut sink)?;
+    }
+    Canon::<S>::write(ret, &mut sink)
+}
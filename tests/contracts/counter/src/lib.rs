@@ -40,11 +40,10 @@ impl Counter {
 mod hosted {
     use super::*;
 
-    use canonical::{BridgeStore, ByteSink, ByteSource, Id32, Store};
+    use call_buffer::{buffer, write_output, HEADER_LEN};
+    use canonical::{BridgeStore, ByteSource, Id32, Store};
     use dusk_abi::{ContractState, ReturnValue};
 
-    const PAGE_SIZE: usize = 1024 * 4;
-
     type BS = BridgeStore<Id32>;
 
     impl Counter {
@@ -82,135 +81,89 @@ mod hosted {
         }
     }
 
-    fn query(bytes: &mut [u8; PAGE_SIZE]) -> Result<(), <BS as Store>::Error> {
+    fn query(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(&bytes[..], &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let slf: Counter = Canon::<BS>::read(&mut source)?;
 
         // read query id
         let qid: u8 = Canon::<BS>::read(&mut source)?;
-        match qid {
+        let ret = match qid {
             // read_value (&Self) -> i32
             READ_VALUE => {
                 let ret = slf.read_value();
-
-                let r = {
-                    // return value
-                    let wrapped_return = ReturnValue::from_canon(&ret, &bs)?;
-
-                    dusk_abi::debug!("wrapped return {:?}", wrapped_return);
-
-                    let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                    Canon::<BS>::write(&wrapped_return, &mut sink)
-                };
-                dusk_abi::debug!("memory bytes {:?}", &bytes[..32]);
-
-                r
+                ReturnValue::from_canon(&ret, &bs)?
             }
             // xor_values (&Self, a: i32, b: i32) -> i32
             XOR_VALUES => {
                 let (a, b): (i32, i32) = Canon::<BS>::read(&mut source)?;
                 let ret = slf.xor_values(a, b);
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-                Canon::<BS>::write(&ret, &mut sink)?;
-                Ok(())
+                ReturnValue::from_canon(&ret, &bs)?
             }
             // is_even (&Self) -> bool
             IS_EVEN => {
                 let ret = slf.is_even();
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                Canon::<BS>::write(&ret, &mut sink)?;
-                Ok(())
+                ReturnValue::from_canon(&ret, &bs)?
             }
             _ => panic!(""),
-        }
+        };
+
+        dusk_abi::debug!("return {:?}", ret);
+
+        write_output(bytes, None, &ret, &bs)
     }
 
     #[no_mangle]
-    fn q(bytes: &mut [u8; PAGE_SIZE]) {
+    fn q(ptr: *mut u8) {
         // todo, handle errors here
-        let _ = query(bytes);
+        let _ = query(buffer(ptr));
     }
 
-    fn transaction(
-        bytes: &mut [u8; PAGE_SIZE],
-    ) -> Result<(), <BS as Store>::Error> {
+    fn transaction(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(bytes, &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let mut slf: Counter = Canon::<BS>::read(&mut source)?;
         // read transaction id
         let tid: u8 = Canon::<BS>::read(&mut source)?;
-        match tid {
+        let ret = match tid {
             // increment (&Self)
             INCREMENT => {
                 slf.increment();
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-                // return new state
-                Canon::<BS>::write(
-                    &ContractState::from_canon(&slf, &bs)?,
-                    &mut sink,
-                )?;
-
-                // return value
-                Canon::<BS>::write(
-                    &ReturnValue::from_canon(&(), &bs)?,
-                    &mut sink,
-                )
+                ReturnValue::from_canon(&(), &bs)?
             }
             DECREMENT => {
                 // no args
                 slf.decrement();
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                Canon::<BS>::write(
-                    &ContractState::from_canon(&slf, &bs),
-                    &mut sink,
-                )?;
-
-                // no return value
-                Ok(())
+                ReturnValue::from_canon(&(), &bs)?
             }
             ADJUST => {
                 // read arg
                 let by: i32 = Canon::<BS>::read(&mut source)?;
                 slf.adjust(by);
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                Canon::<BS>::write(
-                    &ContractState::from_canon(&slf, &bs),
-                    &mut sink,
-                )?;
-
-                // no return value
-                Ok(())
+                ReturnValue::from_canon(&(), &bs)?
             }
             COMPARE_AND_SWAP => {
                 // read multiple args
                 let (a, b): (i32, i32) = Canon::<BS>::read(&mut source)?;
                 let res = slf.compare_and_swap(a, b);
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                Canon::<BS>::write(
-                    &ContractState::from_canon(&slf, &bs),
-                    &mut sink,
-                )?;
-
-                // return result
-                Canon::<BS>::write(&res, &mut sink)
+                ReturnValue::from_canon(&res, &bs)?
             }
             _ => panic!(""),
-        }
+        };
+
+        // return new state
+        let state = ContractState::from_canon(&slf, &bs)?;
+
+        write_output(bytes, Some(&state), &ret, &bs)
     }
 
     #[no_mangle]
-    fn t(bytes: &mut [u8; PAGE_SIZE]) {
+    fn t(ptr: *mut u8) {
         // todo, handle errors here
-        transaction(bytes).unwrap()
+        transaction(buffer(ptr)).unwrap()
     }
 }
@@ -11,9 +11,12 @@ use canonical_derive::Canon;
 
 // qulery ids
 pub const DELEGATE_QUERY: u8 = 0;
+pub const DELEGATE_QUERY_WITH_GAS: u8 = 1;
+pub const DELEGATE_TRANSACTION_IN_QUERY: u8 = 2;
 
 // transaction ids
 pub const DELEGATE_TRANSACTION: u8 = 0;
+pub const DELEGATE_TRANSACTION_WITH_GAS: u8 = 1;
 
 #[derive(Clone, Canon, Debug)]
 pub struct Delegator;
@@ -22,6 +25,7 @@ pub struct Delegator;
 mod hosted {
     use super::*;
 
+    use call_buffer::{buffer, write_output, HEADER_LEN};
     use canonical::{BridgeStore, ByteSink, ByteSource, Canon, Id32, Store};
     use dusk_abi::{
         ContractId, ContractState, Query, ReturnValue, Transaction,
@@ -31,6 +35,17 @@ mod hosted {
 
     type BS = BridgeStore<Id32>;
 
+    mod external {
+        extern "C" {
+            pub fn query_gas(target: &u8, buf: &mut u8, gas_limit: u64) -> i32;
+            pub fn transact_gas(
+                target: &u8,
+                buf: &mut u8,
+                gas_limit: u64,
+            ) -> i32;
+        }
+    }
+
     impl Delegator {
         pub fn delegate_query(
             &self,
@@ -40,6 +55,34 @@ mod hosted {
             dusk_abi::query_raw(target, query).unwrap()
         }
 
+        pub fn delegate_query_with_gas(
+            &self,
+            target: &ContractId,
+            query: &Query,
+            gas_limit: u64,
+        ) -> Option<ReturnValue> {
+            let bs = BS::default();
+            let mut buf = [0u8; PAGE_SIZE];
+
+            let mut sink = ByteSink::new(&mut buf[..], &bs);
+            Canon::<BS>::write(query, &mut sink).unwrap();
+
+            let status = unsafe {
+                external::query_gas(
+                    &target.as_bytes()[0],
+                    &mut buf[0],
+                    gas_limit,
+                )
+            };
+
+            if status == 0 {
+                let mut source = ByteSource::new(&buf[..], &bs);
+                Some(Canon::<BS>::read(&mut source).unwrap())
+            } else {
+                None
+            }
+        }
+
         pub fn delegate_transaction(
             &mut self,
             target: &ContractId,
@@ -49,74 +92,125 @@ mod hosted {
                 dusk_abi::transact_raw(target, transaction).unwrap();
             result
         }
+
+        pub fn delegate_transaction_with_gas(
+            &mut self,
+            target: &ContractId,
+            transaction: &Transaction,
+            gas_limit: u64,
+        ) -> Option<ReturnValue> {
+            let bs = BS::default();
+            let mut buf = [0u8; PAGE_SIZE];
+
+            let mut sink = ByteSink::new(&mut buf[..], &bs);
+            Canon::<BS>::write(transaction, &mut sink).unwrap();
+
+            let status = unsafe {
+                external::transact_gas(
+                    &target.as_bytes()[0],
+                    &mut buf[0],
+                    gas_limit,
+                )
+            };
+
+            if status == 0 {
+                let mut source = ByteSource::new(&buf[..], &bs);
+                let (_, result): (ContractState, ReturnValue) =
+                    Canon::<BS>::read(&mut source).unwrap();
+                Some(result)
+            } else {
+                None
+            }
+        }
     }
 
-    fn query(bytes: &mut [u8; PAGE_SIZE]) -> Result<(), <BS as Store>::Error> {
+    fn query(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(&bytes[..], &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let slf: Delegator = Canon::<BS>::read(&mut source)?;
 
         // read query id
         let qid: u8 = Canon::<BS>::read(&mut source)?;
-        match qid {
+        let ret = match qid {
             DELEGATE_QUERY => {
                 let (target, query): (ContractId, Query) =
                     Canon::read(&mut source)?;
 
-                let result = slf.delegate_query(&target, &query);
+                slf.delegate_query(&target, &query)
+            }
+            DELEGATE_QUERY_WITH_GAS => {
+                let (target, query, gas_limit): (ContractId, Query, u64) =
+                    Canon::read(&mut source)?;
 
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
+                let result =
+                    slf.delegate_query_with_gas(&target, &query, gas_limit);
+
+                ReturnValue::from_canon(&result, &bs)?
+            }
+            DELEGATE_TRANSACTION_IN_QUERY => {
+                let (target, transaction): (ContractId, Transaction) =
+                    Canon::read(&mut source)?;
 
-                Canon::<BS>::write(&result, &mut sink)?;
-                Ok(())
+                let (_, result) =
+                    dusk_abi::transact_raw(&target, &transaction).unwrap();
+                result
             }
             _ => panic!(""),
-        }
+        };
+
+        write_output(bytes, None, &ret, &bs)
     }
 
     #[no_mangle]
-    fn q(bytes: &mut [u8; PAGE_SIZE]) {
+    fn q(ptr: *mut u8) {
         // todo, handle errors here
-        let _ = query(bytes);
+        let _ = query(buffer(ptr));
     }
 
-    fn transaction(
-        bytes: &mut [u8; PAGE_SIZE],
-    ) -> Result<(), <BS as Store>::Error> {
+    fn transaction(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(bytes, &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let mut slf: Delegator = Canon::<BS>::read(&mut source)?;
         // read transaction id
         let tid: u8 = Canon::<BS>::read(&mut source)?;
-        match tid {
+        let result = match tid {
             DELEGATE_TRANSACTION => {
                 let (target, transaction): (ContractId, Transaction) =
                     Canon::read(&mut source)?;
 
-                let result = slf.delegate_transaction(&target, &transaction);
-
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                // return new state
-                Canon::<BS>::write(
-                    &ContractState::from_canon(&slf, &bs)?,
-                    &mut sink,
-                )?;
-
-                // return value
-                Canon::<BS>::write(&result, &mut sink)
+                slf.delegate_transaction(&target, &transaction)
+            }
+            DELEGATE_TRANSACTION_WITH_GAS => {
+                let (target, transaction, gas_limit): (
+                    ContractId,
+                    Transaction,
+                    u64,
+                ) = Canon::read(&mut source)?;
+
+                let result = slf.delegate_transaction_with_gas(
+                    &target,
+                    &transaction,
+                    gas_limit,
+                );
+
+                ReturnValue::from_canon(&result, &bs)?
             }
             _ => panic!(""),
-        }
+        };
+
+        // return new state
+        let state = ContractState::from_canon(&slf, &bs)?;
+
+        write_output(bytes, Some(&state), &result, &bs)
     }
 
     #[no_mangle]
-    fn t(bytes: &mut [u8; PAGE_SIZE]) {
+    fn t(ptr: *mut u8) {
         // todo, handle errors here
-        transaction(bytes).unwrap()
+        transaction(buffer(ptr)).unwrap()
     }
 }
@@ -19,11 +19,10 @@ pub struct Fibonacci;
 mod hosted {
     use super::*;
 
-    use canonical::{BridgeStore, ByteSink, ByteSource, Canon, Id32, Store};
+    use call_buffer::{buffer, write_output, HEADER_LEN};
+    use canonical::{BridgeStore, ByteSource, Canon, Id32, Store};
     use dusk_abi::ReturnValue;
 
-    const PAGE_SIZE: usize = 1024 * 4;
-
     type BS = BridgeStore<Id32>;
 
     impl Fibonacci {
@@ -44,9 +43,9 @@ mod hosted {
         }
     }
 
-    fn query(bytes: &mut [u8; PAGE_SIZE]) -> Result<(), <BS as Store>::Error> {
+    fn query(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let store = BS::default();
-        let mut source = ByteSource::new(&bytes[..], &store);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &store);
 
         // read self (noop).
         let slf: Fibonacci = Canon::<BS>::read(&mut source)?;
@@ -61,20 +60,25 @@ mod hosted {
 
                 let ret = slf.compute(input);
 
-                let mut sink = ByteSink::new(&mut bytes[..], &store);
                 let packed_ret = ReturnValue::from_canon(&ret, &store)?;
 
                 dusk_abi::debug!("packed_ret {:?}", packed_ret);
 
-                Canon::<BS>::write(&packed_ret, &mut sink)
+                write_output(bytes, None, &packed_ret, &store)
             }
             _ => panic!(""),
         }
     }
 
     #[no_mangle]
-    fn q(bytes: &mut [u8; PAGE_SIZE]) {
+    fn q(ptr: *mut u8) {
         // todo, handle errors here
-        let _ = query(bytes);
+        let _ = query(buffer(ptr));
+    }
+
+    #[no_mangle]
+    fn t(_: *mut u8) {
+        // the contract has no transactions
+        panic!("")
     }
 }
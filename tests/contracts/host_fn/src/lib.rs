@@ -32,13 +32,12 @@ mod hosted {
 
     use alloc::vec::Vec;
 
-    use canonical::{BridgeStore, ByteSink, ByteSource, Canon, Id32, Store};
+    use call_buffer::{buffer, write_output, HEADER_LEN};
+    use canonical::{BridgeStore, ByteSource, Canon, Id32, Store};
     use dusk_abi::{ContractId, ReturnValue};
 
     use dusk_bls12_381::BlsScalar;
 
-    const PAGE_SIZE: usize = 1024 * 4;
-
     type BS = BridgeStore<Id32>;
 
     impl HostFnTest {
@@ -50,9 +49,9 @@ mod hosted {
         }
     }
 
-    fn query(bytes: &mut [u8; PAGE_SIZE]) -> Result<(), <BS as Store>::Error> {
+    fn query(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(&bytes[..], &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let slf: HostFnTest = Canon::<BS>::read(&mut source)?;
@@ -66,32 +65,24 @@ mod hosted {
 
                 let ret = slf.hash(arg);
 
-                let r = {
-                    // return value
-                    let wrapped_return = ReturnValue::from_canon(&ret, &bs)?;
-
-                    let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                    Canon::<BS>::write(&wrapped_return, &mut sink)
-                };
+                // return value
+                let wrapped_return = ReturnValue::from_canon(&ret, &bs)?;
 
-                r
+                write_output(bytes, None, &wrapped_return, &bs)
             }
             _ => panic!(""),
         }
     }
 
     #[no_mangle]
-    fn q(bytes: &mut [u8; PAGE_SIZE]) {
+    fn q(ptr: *mut u8) {
         // todo, handle errors here
-        let _ = query(bytes);
+        let _ = query(buffer(ptr));
     }
 
-    fn transaction(
-        bytes: &mut [u8; PAGE_SIZE],
-    ) -> Result<(), <BS as Store>::Error> {
+    fn transaction(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(bytes, &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let mut _slf: HostFnTest = Canon::<BS>::read(&mut source)?;
@@ -103,8 +94,8 @@ mod hosted {
     }
 
     #[no_mangle]
-    fn t(bytes: &mut [u8; PAGE_SIZE]) {
+    fn t(ptr: *mut u8) {
         // todo, handle errors here
-        transaction(bytes).unwrap()
+        transaction(buffer(ptr)).unwrap()
     }
 }
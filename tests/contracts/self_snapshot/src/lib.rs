@@ -12,12 +12,16 @@ use canonical_derive::Canon;
 
 // query ids
 pub const CROSSOVER: u8 = 0;
+pub const REENTERED: u8 = 1;
+pub const SELF_REENTERED: u8 = 2;
+pub const DELEGATED_REENTERED: u8 = 3;
 
 // transaction ids
 pub const SET_CROSSOVER: u8 = 0;
 pub const SELF_CALL_TEST_A: u8 = 1;
 pub const SELF_CALL_TEST_B: u8 = 2;
 pub const UPDATE_AND_PANIC: u8 = 3;
+pub const UPDATE_AND_LOOP: u8 = 4;
 
 #[derive(Clone, Canon, Debug)]
 pub struct SelfSnapshot {
@@ -34,13 +38,21 @@ impl SelfSnapshot {
 mod hosted {
     use super::*;
 
-    use canonical::{BridgeStore, ByteSink, ByteSource, Canon, Id32, Store};
-    use dusk_abi::{ContractState, ReturnValue};
+    use call_buffer::{buffer, write_output, HEADER_LEN};
+    use canonical::{BridgeStore, ByteSource, Canon, Id32, Store};
+    use dusk_abi::{ContractId, ContractState, ReturnValue};
 
-    const PAGE_SIZE: usize = 1024 * 4;
+    // query id of the delegator contract, which is not a dependency
+    const DELEGATE_QUERY: u8 = 0;
 
     type BS = BridgeStore<Id32>;
 
+    mod external {
+        extern "C" {
+            pub fn reentered() -> i32;
+        }
+    }
+
     impl SelfSnapshot {
         pub fn crossover(&self) -> i32 {
             self.crossover
@@ -94,11 +106,44 @@ mod hosted {
 
             panic!("OH NOES")
         }
+
+        // updates crossover through a self-call, and then loops until it runs
+        // out of gas
+        pub fn update_and_loop(&mut self, new_value: i32) {
+            self.self_call_test_a(new_value);
+
+            loop {
+                unsafe { core::ptr::read_volatile(&self.crossover) };
+            }
+        }
+
+        pub fn reentered(&self) -> bool {
+            unsafe { external::reentered() != 0 }
+        }
+
+        // queries whether self is re-entered from a call to self
+        pub fn self_reentered(&self) -> bool {
+            let callee = dusk_abi::callee();
+
+            dusk_abi::query::<_, bool>(&callee, &REENTERED).unwrap()
+        }
+
+        // queries whether self is re-entered from a call to self delegated
+        // by the `delegator` contract
+        pub fn delegated_reentered(&self, delegator: &ContractId) -> bool {
+            let callee = dusk_abi::callee();
+
+            dusk_abi::query::<_, bool>(
+                delegator,
+                &(DELEGATE_QUERY, callee, REENTERED),
+            )
+            .unwrap()
+        }
     }
 
-    fn query(bytes: &mut [u8; PAGE_SIZE]) -> Result<(), <BS as Store>::Error> {
+    fn query(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let store = BS::default();
-        let mut source = ByteSource::new(&bytes[..], &store);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &store);
 
         // read self (noop).
         let slf: SelfSnapshot = Canon::<BS>::read(&mut source)?;
@@ -109,85 +154,89 @@ mod hosted {
             CROSSOVER => {
                 let ret = slf.crossover();
 
-                let mut sink = ByteSink::new(&mut bytes[..], &store);
                 let packed_ret = ReturnValue::from_canon(&ret, &store)?;
 
-                Canon::<BS>::write(&packed_ret, &mut sink)
+                write_output(bytes, None, &packed_ret, &store)
+            }
+            REENTERED => {
+                let ret = slf.reentered();
+
+                let packed_ret = ReturnValue::from_canon(&ret, &store)?;
+
+                write_output(bytes, None, &packed_ret, &store)
+            }
+            SELF_REENTERED => {
+                let ret = slf.self_reentered();
+
+                let packed_ret = ReturnValue::from_canon(&ret, &store)?;
+
+                write_output(bytes, None, &packed_ret, &store)
+            }
+            DELEGATED_REENTERED => {
+                let delegator: ContractId = Canon::<BS>::read(&mut source)?;
+
+                let ret = slf.delegated_reentered(&delegator);
+
+                let packed_ret = ReturnValue::from_canon(&ret, &store)?;
+
+                write_output(bytes, None, &packed_ret, &store)
             }
             _ => panic!(""),
         }
     }
 
     #[no_mangle]
-    fn q(bytes: &mut [u8; PAGE_SIZE]) {
+    fn q(ptr: *mut u8) {
         // todo, handle errors here
-        let _ = query(bytes);
+        let _ = query(buffer(ptr));
     }
 
-    fn transaction(
-        bytes: &mut [u8; PAGE_SIZE],
-    ) -> Result<(), <BS as Store>::Error> {
+    fn transaction(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(bytes, &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let mut slf: SelfSnapshot = Canon::<BS>::read(&mut source)?;
         // read transaction id
         let tid: u8 = Canon::<BS>::read(&mut source)?;
-        match tid {
+        let ret = match tid {
             // increment (&Self)
             SET_CROSSOVER => {
                 let to: i32 = Canon::<BS>::read(&mut source)?;
                 let old = slf.set_crossover(to);
 
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-                // return new state
-                Canon::<BS>::write(
-                    &ContractState::from_canon(&slf, &bs)?,
-                    &mut sink,
-                )?;
-
-                // return value
-                Canon::<BS>::write(
-                    &ReturnValue::from_canon(&old, &bs)?,
-                    &mut sink,
-                )
+                ReturnValue::from_canon(&old, &bs)?
             }
             SELF_CALL_TEST_A => {
                 let update: i32 = Canon::<BS>::read(&mut source)?;
                 let old = slf.self_call_test_a(update);
 
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-                // return new state
-                Canon::<BS>::write(
-                    &ContractState::from_canon(&slf, &bs)?,
-                    &mut sink,
-                )?;
-
-                // return value
-                Canon::<BS>::write(
-                    &ReturnValue::from_canon(&old, &bs)?,
-                    &mut sink,
-                )
+                ReturnValue::from_canon(&old, &bs)?
             }
             UPDATE_AND_PANIC => {
                 let update: i32 = Canon::<BS>::read(&mut source)?;
                 slf.update_and_panic(update);
 
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-                // return new state
-                Canon::<BS>::write(
-                    &ContractState::from_canon(&slf, &bs)?,
-                    &mut sink,
-                )
+                ReturnValue::from_canon(&(), &bs)?
+            }
+            UPDATE_AND_LOOP => {
+                let update: i32 = Canon::<BS>::read(&mut source)?;
+                slf.update_and_loop(update);
+
+                ReturnValue::from_canon(&(), &bs)?
             }
             _ => panic!(""),
-        }
+        };
+
+        // return new state
+        let state = ContractState::from_canon(&slf, &bs)?;
+
+        write_output(bytes, Some(&state), &ret, &bs)
     }
 
     #[no_mangle]
-    fn t(bytes: &mut [u8; PAGE_SIZE]) {
+    fn t(ptr: *mut u8) {
         // todo, handle errors here
-        transaction(bytes).unwrap()
+        transaction(buffer(ptr)).unwrap()
     }
 }
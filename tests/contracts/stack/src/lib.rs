@@ -41,11 +41,10 @@ impl<S: Store> Stack<S> {
 mod hosted {
     use super::*;
 
-    use canonical::{BridgeStore, ByteSink, ByteSource, Id32, Store};
+    use call_buffer::{buffer, write_output, HEADER_LEN};
+    use canonical::{BridgeStore, ByteSource, Id32, Store};
     use dusk_abi::{ContractState, ReturnValue};
 
-    const PAGE_SIZE: usize = 1024 * 4;
-
     type BS = BridgeStore<Id32>;
 
     impl<S: Store> Stack<S> {
@@ -58,9 +57,9 @@ mod hosted {
         }
     }
 
-    fn query(bytes: &mut [u8; PAGE_SIZE]) -> Result<(), <BS as Store>::Error> {
+    fn query(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(&bytes[..], &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let slf: Stack<BS> = Canon::<BS>::read(&mut source)?;
@@ -73,32 +72,24 @@ mod hosted {
 
                 let ret = slf.peek(arg);
 
-                let r = {
-                    // return value
-                    let wrapped_return = ReturnValue::from_canon(&ret, &bs)?;
-
-                    let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                    Canon::<BS>::write(&wrapped_return, &mut sink)
-                };
+                // return value
+                let wrapped_return = ReturnValue::from_canon(&ret, &bs)?;
 
-                r
+                write_output(bytes, None, &wrapped_return, &bs)
             }
             _ => panic!(""),
         }
     }
 
     #[no_mangle]
-    fn q(bytes: &mut [u8; PAGE_SIZE]) {
+    fn q(ptr: *mut u8) {
         // todo, handle errors here
-        let _ = query(bytes);
+        let _ = query(buffer(ptr));
     }
 
-    fn transaction(
-        bytes: &mut [u8; PAGE_SIZE],
-    ) -> Result<(), <BS as Store>::Error> {
+    fn transaction(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(bytes, &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let mut slf: Stack<BS> = Canon::<BS>::read(&mut source)?;
@@ -109,42 +100,29 @@ mod hosted {
                 let value: i32 = Canon::<BS>::read(&mut source)?;
                 slf.push(value);
 
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
                 let new_state = ContractState::from_canon(&slf, &bs)?;
 
-                // return new state
-                Canon::<BS>::write(&new_state, &mut sink)?;
-
-                let ret_val = ReturnValue::from_canon(&(), &bs);
-
                 // return value (no-op)
-                Canon::<BS>::write(&ret_val, &mut sink)
+                let ret_val = ReturnValue::from_canon(&(), &bs)?;
+
+                write_output(bytes, Some(&new_state), &ret_val, &bs)
             }
             POP => {
                 let result = slf.pop();
 
-                let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
                 let new_state = ContractState::from_canon(&slf, &bs)?;
 
-                // return new self
-                Canon::<BS>::write(&new_state, &mut sink)?;
-
                 let return_value = ReturnValue::from_canon(&result, &bs)?;
 
-                // return value
-                Canon::<BS>::write(&return_value, &mut sink)?;
-
-                Ok(())
+                write_output(bytes, Some(&new_state), &return_value, &bs)
             }
             _ => panic!(""),
         }
     }
 
     #[no_mangle]
-    fn t(bytes: &mut [u8; PAGE_SIZE]) {
+    fn t(ptr: *mut u8) {
         // todo, handle errors here
-        transaction(bytes).unwrap()
+        transaction(buffer(ptr)).unwrap()
     }
 }
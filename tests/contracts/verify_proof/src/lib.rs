@@ -29,11 +29,10 @@ mod hosted {
     use super::*;
     use alloc::vec::Vec;
 
-    use canonical::{BridgeStore, ByteSink, ByteSource, Canon, Id32, Store};
+    use call_buffer::{buffer, write_output, HEADER_LEN};
+    use canonical::{BridgeStore, ByteSource, Canon, Id32, Store};
     use dusk_abi::ReturnValue;
 
-    const PAGE_SIZE: usize = 1024 * 4;
-
     type BS = BridgeStore<Id32>;
 
     impl ProofVerifier {
@@ -48,9 +47,9 @@ mod hosted {
         }
     }
 
-    fn query(bytes: &mut [u8; PAGE_SIZE]) -> Result<(), <BS as Store>::Error> {
+    fn query(bytes: &mut [u8]) -> Result<(), <BS as Store>::Error> {
         let bs = BS::default();
-        let mut source = ByteSource::new(&bytes[..], &bs);
+        let mut source = ByteSource::new(&bytes[HEADER_LEN..], &bs);
 
         // read self.
         let slf: ProofVerifier = Canon::<BS>::read(&mut source)?;
@@ -65,24 +64,24 @@ mod hosted {
                 let pub_inp: Vec<u8> = Canon::<BS>::read(&mut source)?;
                 let ret = slf.verify_proof(proof, vk, label, pub_inp);
 
-                let r = {
-                    // return value
-                    let wrapped_return = ReturnValue::from_canon(&ret, &bs)?;
-
-                    let mut sink = ByteSink::new(&mut bytes[..], &bs);
-
-                    Canon::<BS>::write(&wrapped_return, &mut sink)
-                };
+                // return value
+                let wrapped_return = ReturnValue::from_canon(&ret, &bs)?;
 
-                r
+                write_output(bytes, None, &wrapped_return, &bs)
             }
             _ => panic!(""),
         }
     }
 
     #[no_mangle]
-    fn q(bytes: &mut [u8; PAGE_SIZE]) {
+    fn q(ptr: *mut u8) {
         // todo, handle errors here
-        let _ = query(bytes);
+        let _ = query(buffer(ptr));
+    }
+
+    #[no_mangle]
+    fn t(_: *mut u8) {
+        // the contract has no transactions
+        panic!("")
     }
 }
@@ -0,0 +1,403 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Runs the test contracts on every engine, checking they all produce the
+//! same results and spend the same gas.
+
+use super::*;
+
+use rusk_vm::EngineKind;
+
+// Runs `scenario` against a fresh network on both engines, and compares the
+// results of the calls it made, along with the gas they spent.
+fn differential<F>(scenario: F)
+where
+    F: Fn(&mut NetworkState<MS>, &MS, &mut Vec<String>),
+{
+    let run = |engine| {
+        let store = MS::new();
+
+        let mut network = NetworkState::<MS>::with_block_height(99);
+        network.set_engine(engine);
+        network.register_host_module(PoseidonModule::new(store.clone()));
+
+        let mut results = vec![];
+        scenario(&mut network, &store, &mut results);
+        results
+    };
+
+    let wasmi = run(EngineKind::Wasmi);
+    let wasmtime = run(EngineKind::Wasmtime);
+
+    assert_eq!(wasmi, wasmtime);
+}
+
+// Records the result of a call along with the gas it spent.
+fn record<R: std::fmt::Debug>(
+    results: &mut Vec<String>,
+    gas: GasMeter,
+    result: Result<R, VMError<MS>>,
+) {
+    results.push(format!("{:?} ({} gas)", result, gas.spent()));
+}
+
+fn deploy<C: Canon<MS>>(
+    network: &mut NetworkState<MS>,
+    store: &MS,
+    state: C,
+    code: &[u8],
+) -> ContractId {
+    let contract = Contract::new(state, code.to_vec(), store).unwrap();
+    network.deploy(contract).unwrap()
+}
+
+#[test]
+fn counter() {
+    differential(|network, store, results| {
+        let id = deploy(
+            network,
+            store,
+            Counter::new(99),
+            include_bytes!("../contracts/counter/counter.wasm"),
+        );
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.query::<_, i32>(id, counter::READ_VALUE, &mut gas);
+        record(results, gas, result);
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.query::<_, i32>(
+            id,
+            (counter::XOR_VALUES, 0x0f0f, 0x00ff),
+            &mut gas,
+        );
+        record(results, gas, result);
+
+        for transaction in &[counter::INCREMENT, counter::DECREMENT] {
+            let mut gas = GasMeter::with_limit(1_000_000_000);
+            let result = network.transact::<_, ()>(id, *transaction, &mut gas);
+            record(results, gas, result);
+        }
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result =
+            network.transact::<_, ()>(id, (counter::ADJUST, -7), &mut gas);
+        record(results, gas, result);
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.transact::<_, bool>(
+            id,
+            (counter::COMPARE_AND_SWAP, 92, 5),
+            &mut gas,
+        );
+        record(results, gas, result);
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.query::<_, bool>(id, counter::IS_EVEN, &mut gas);
+        record(results, gas, result);
+    });
+}
+
+#[test]
+fn fibonacci() {
+    differential(|network, store, results| {
+        let id = deploy(
+            network,
+            store,
+            Fibonacci,
+            include_bytes!("../contracts/fibonacci/fibonacci.wasm"),
+        );
+
+        for n in 0..12u64 {
+            let mut gas = GasMeter::with_limit(1_000_000_000);
+            let result =
+                network.query::<_, u64>(id, (fibonacci::COMPUTE, n), &mut gas);
+            record(results, gas, result);
+        }
+    });
+}
+
+#[test]
+fn out_of_gas() {
+    differential(|network, store, results| {
+        let id = deploy(
+            network,
+            store,
+            Fibonacci,
+            include_bytes!("../contracts/fibonacci/fibonacci.wasm"),
+        );
+
+        for limit in &[0, 300, 1_000, 10_000] {
+            let mut gas = GasMeter::with_limit(*limit);
+            let result = network.query::<_, u64>(
+                id,
+                (fibonacci::COMPUTE, 20u64),
+                &mut gas,
+            );
+            record(results, gas, result);
+        }
+    });
+}
+
+#[test]
+fn stack_overflow() {
+    differential(|network, store, results| {
+        network.add_schedule(
+            0,
+            Schedule {
+                max_stack_height: 1024,
+                ..Schedule::default()
+            },
+        );
+
+        let id = deploy(
+            network,
+            store,
+            Fibonacci,
+            include_bytes!("../contracts/fibonacci/fibonacci.wasm"),
+        );
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.query::<_, u64>(
+            id,
+            (fibonacci::COMPUTE, 1_000u64),
+            &mut gas,
+        );
+        record(results, gas, result);
+    });
+}
+
+#[test]
+fn delegator() {
+    differential(|network, store, results| {
+        let counter_id = deploy(
+            network,
+            store,
+            Counter::new(99),
+            include_bytes!("../contracts/counter/counter.wasm"),
+        );
+        let fib_id = deploy(
+            network,
+            store,
+            Fibonacci,
+            include_bytes!("../contracts/fibonacci/fibonacci.wasm"),
+        );
+        let id = deploy(
+            network,
+            store,
+            Delegator,
+            include_bytes!("../contracts/delegator/delegator.wasm"),
+        );
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.query::<_, i32>(
+            id,
+            (delegator::DELEGATE_QUERY, counter_id, counter::READ_VALUE),
+            &mut gas,
+        );
+        record(results, gas, result);
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.transact::<_, ()>(
+            id,
+            (
+                delegator::DELEGATE_TRANSACTION,
+                counter_id,
+                counter::INCREMENT,
+            ),
+            &mut gas,
+        );
+        record(results, gas, result);
+
+        let query =
+            Query::from_canon(&(fibonacci::COMPUTE, 10u64), store).unwrap();
+
+        for gas_limit in &[1_000_000u64, 1_000] {
+            let mut gas = GasMeter::with_limit(1_000_000_000);
+            let result = network.query::<_, Option<ReturnValue>>(
+                id,
+                (
+                    delegator::DELEGATE_QUERY_WITH_GAS,
+                    fib_id,
+                    query.clone(),
+                    *gas_limit,
+                ),
+                &mut gas,
+            );
+            record(results, gas, result);
+        }
+    });
+}
+
+#[test]
+fn stack() {
+    differential(|network, store, results| {
+        let id = deploy(
+            network,
+            store,
+            Stack::<MS>::new(),
+            include_bytes!("../contracts/stack/stack.wasm"),
+        );
+
+        for i in 0..16i32 {
+            let mut gas = GasMeter::with_limit(1_000_000_000);
+            let result =
+                network.transact::<_, ()>(id, (stack::PUSH, i), &mut gas);
+            record(results, gas, result);
+        }
+
+        for i in 0..17i32 {
+            let mut gas = GasMeter::with_limit(1_000_000_000);
+            let result =
+                network.query::<_, Option<i32>>(id, (stack::PEEK, i), &mut gas);
+            record(results, gas, result);
+        }
+
+        for _ in 0..17 {
+            let mut gas = GasMeter::with_limit(1_000_000_000);
+            let result =
+                network.transact::<_, Option<i32>>(id, stack::POP, &mut gas);
+            record(results, gas, result);
+        }
+    });
+}
+
+#[test]
+fn block_height() {
+    differential(|network, store, results| {
+        let id = deploy(
+            network,
+            store,
+            BlockHeight::new(),
+            include_bytes!("../contracts/block_height/block_height.wasm"),
+        );
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result =
+            network.query::<_, u64>(id, block_height::BLOCK_HEIGHT, &mut gas);
+        record(results, gas, result);
+    });
+}
+
+#[test]
+fn self_snapshot() {
+    differential(|network, store, results| {
+        let id = deploy(
+            network,
+            store,
+            SelfSnapshot::new(7),
+            include_bytes!("../contracts/self_snapshot/self_snapshot.wasm"),
+        );
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.transact::<_, i32>(
+            id,
+            (self_snapshot::SET_CROSSOVER, 9),
+            &mut gas,
+        );
+        record(results, gas, result);
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.transact::<_, ()>(
+            id,
+            (self_snapshot::SELF_CALL_TEST_A, 10),
+            &mut gas,
+        );
+        record(results, gas, result);
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.transact::<_, ()>(
+            id,
+            (self_snapshot::UPDATE_AND_PANIC, 11),
+            &mut gas,
+        );
+        record(results, gas, result);
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result =
+            network.query::<_, i32>(id, self_snapshot::CROSSOVER, &mut gas);
+        record(results, gas, result);
+    });
+}
+
+#[test]
+fn host_fn() {
+    differential(|network, store, results| {
+        let id = deploy(
+            network,
+            store,
+            HostFnTest::new(),
+            include_bytes!("../contracts/host_fn/host_fn.wasm"),
+        );
+
+        let inputs = vec![BlsScalar::from(1u64), BlsScalar::from(2u64)];
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        let result = network.query::<_, BlsScalar>(
+            id,
+            (host_fn::HASH, inputs),
+            &mut gas,
+        );
+        record(results, gas, result);
+    });
+}
+
+// Traps in the way given as argument to its query: 0 reaches `unreachable`,
+// 1 divides by zero, 2 loads out of bounds, 3 overflows a signed division
+// and 4 calls a null table element
+const TRAPS: &str = r#"
+    (module
+      (type $void (func))
+      (memory (export "memory") 1)
+      (table 1 funcref)
+      (func (export "q") (param $buffer i32)
+        (local $kind i32)
+        (local.set $kind (i32.load offset=8 (local.get $buffer)))
+        (if (i32.eq (local.get $kind) (i32.const 0))
+          (then unreachable))
+        (if (i32.eq (local.get $kind) (i32.const 1))
+          (then (drop (i32.div_u (i32.const 1) (i32.const 0)))))
+        (if (i32.eq (local.get $kind) (i32.const 2))
+          (then (drop (i32.load (i32.const -1)))))
+        (if (i32.eq (local.get $kind) (i32.const 3))
+          (then (drop (i32.div_s (i32.const 0x80000000) (i32.const -1)))))
+        (if (i32.eq (local.get $kind) (i32.const 4))
+          (then (call_indirect (type $void) (i32.const 0))))
+        (i32.store (local.get $buffer) (i32.const 0))
+        (i32.store offset=4 (local.get $buffer) (i32.const 0)))
+      (func (export "t") (param i32)
+        unreachable))
+"#;
+
+#[test]
+fn traps() {
+    differential(|network, store, results| {
+        network.add_schedule(
+            0,
+            Schedule {
+                max_memory_pages: 4,
+                ..Schedule::default()
+            },
+        );
+
+        let id = deploy(network, store, (), &wat::parse_str(TRAPS).unwrap());
+
+        for kind in 0..5u32 {
+            let mut gas = GasMeter::with_limit(1_000_000_000);
+            let result = network.query::<_, ()>(id, kind, &mut gas);
+            record(results, gas, result);
+        }
+
+        let id =
+            deploy(network, store, (), &wat::parse_str(MEMORY_GROW).unwrap());
+
+        for pages in &[2u32, 3] {
+            let mut gas = GasMeter::with_limit(1_000_000_000);
+            let result = network.query::<_, ()>(id, *pages, &mut gas);
+            record(results, gas, result);
+        }
+    });
+}
@@ -5,15 +5,21 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 mod contracts;
+#[cfg(feature = "wasmtime")]
+mod differential;
 
-use rusk_vm::{Contract, ContractId, GasMeter, NetworkState};
+use rusk_vm::{
+    verify_proof, BlockTransaction, Contract, ContractId, ContractProof,
+    DiskError, DiskStore, Fee, GasMeter, GasProfiler, InvalidModule,
+    NetworkState, ReentrancyPolicy, Schedule, VMError,
+};
 
 use dusk_bls12_381::BlsScalar;
 use dusk_bytes::ParseHexStr;
 
-use canonical::{ByteSource, Canon, Store};
+use canonical::{ByteSink, ByteSource, Canon, Ident, Store};
 use canonical_host::MemStore as MS;
-use dusk_abi::{HostModule, Module, Query, ReturnValue};
+use dusk_abi::{HostModule, Module, Query, ReturnValue, Transaction};
 
 use block_height::BlockHeight;
 use counter::Counter;
@@ -90,6 +96,259 @@ fn counter_trivial() {
     );
 }
 
+#[test]
+fn gas_estimation() {
+    let counter = Counter::new(99);
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+
+    let contract = Contract::new(counter, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let estimate = network
+        .estimate_gas_query(contract_id, counter::READ_VALUE, 1_000_000_000)
+        .unwrap();
+
+    let mut gas = GasMeter::with_limit(estimate - 1);
+    assert!(network
+        .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+        .is_err());
+
+    let mut gas = GasMeter::with_limit(estimate);
+    assert_eq!(
+        network
+            .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+            .unwrap(),
+        99
+    );
+
+    let estimate = network
+        .estimate_gas_transact(contract_id, counter::INCREMENT, 1_000_000_000)
+        .unwrap();
+
+    // estimating does not change the state
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+    assert_eq!(
+        network
+            .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+            .unwrap(),
+        99
+    );
+
+    let mut gas = GasMeter::with_limit(estimate);
+    network
+        .transact::<_, ()>(contract_id, counter::INCREMENT, &mut gas)
+        .unwrap();
+}
+
+#[test]
+fn gas_estimation_delegated() {
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let fib_code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+    let fib_contract =
+        Contract::new(Fibonacci, fib_code.to_vec(), &store).unwrap();
+    let fib_id = network.deploy(fib_contract).unwrap();
+
+    let delegator_code = include_bytes!("contracts/delegator/delegator.wasm");
+    let delegator_contract =
+        Contract::new(Delegator, delegator_code.to_vec(), &store).unwrap();
+    let delegator_id = network.deploy(delegator_contract).unwrap();
+
+    let n: u64 = 5;
+    let query = Query::from_canon(&(fibonacci::COMPUTE, n), &store).unwrap();
+
+    // the callee is given a budget it can complete with, which is capped by
+    // the gas left to the delegator, and the estimate must leave it enough
+    // to succeed
+
+    let delegated = (
+        delegator::DELEGATE_QUERY_WITH_GAS,
+        fib_id,
+        query.clone(),
+        1_000_000u64,
+    );
+
+    let estimate = network
+        .estimate_gas_query(delegator_id, delegated.clone(), 1_000_000_000)
+        .unwrap();
+
+    let mut gas = GasMeter::with_limit(estimate - 1);
+    assert!(network
+        .query::<_, Option<ReturnValue>>(
+            delegator_id,
+            delegated.clone(),
+            &mut gas
+        )
+        .is_err());
+
+    let mut gas = GasMeter::with_limit(estimate);
+    let result = network
+        .query::<_, Option<ReturnValue>>(delegator_id, delegated, &mut gas)
+        .unwrap()
+        .expect("the callee to succeed");
+
+    let value: u64 = result.cast(store.clone()).unwrap();
+    assert_eq!(value, 5);
+
+    // the callee runs out of its budget, which the estimate still covers
+
+    let delegated =
+        (delegator::DELEGATE_QUERY_WITH_GAS, fib_id, query, 1_000u64);
+
+    let estimate = network
+        .estimate_gas_query(delegator_id, delegated.clone(), 1_000_000_000)
+        .unwrap();
+
+    let mut gas = GasMeter::with_limit(estimate - 1);
+    assert!(network
+        .query::<_, Option<ReturnValue>>(
+            delegator_id,
+            delegated.clone(),
+            &mut gas
+        )
+        .is_err());
+
+    let mut gas = GasMeter::with_limit(estimate);
+    assert!(network
+        .query::<_, Option<ReturnValue>>(delegator_id, delegated, &mut gas)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn transaction_fees() {
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+    let contract =
+        Contract::new(Counter::new(99), code.to_vec(), &store).unwrap();
+    let contract_id = network.deploy(contract).unwrap();
+
+    let payer = ContractId::reserved(42);
+    let gas_price = 3;
+
+    network.deposit(payer, 1_000_000_000).unwrap();
+
+    // unused gas is refunded
+
+    let receipt = network
+        .transact_with_fee::<_, ()>(
+            payer,
+            contract_id,
+            counter::INCREMENT,
+            Fee::new(1_000_000, gas_price),
+        )
+        .unwrap();
+
+    assert!(receipt.result().is_ok());
+    assert_eq!(receipt.fee(), receipt.gas_spent() * gas_price);
+    assert_eq!(
+        network.balance(&payer).unwrap(),
+        1_000_000_000 - receipt.fee()
+    );
+
+    // failed transactions are charged, but do not change the state
+
+    let balance = network.balance(&payer).unwrap();
+
+    let receipt = network
+        .transact_with_fee::<_, ()>(
+            payer,
+            contract_id,
+            counter::INCREMENT,
+            Fee::new(10, gas_price),
+        )
+        .unwrap();
+
+    assert!(matches!(receipt.result(), Err(VMError::OutOfGas)));
+    assert_eq!(receipt.fee(), 10 * gas_price);
+    assert_eq!(network.balance(&payer).unwrap(), balance - 10 * gas_price);
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    assert_eq!(
+        network
+            .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+            .unwrap(),
+        100
+    );
+
+    // the maximum fee must be covered by the payer
+
+    let balance = network.balance(&payer).unwrap();
+
+    let result = network.transact_with_fee::<_, ()>(
+        payer,
+        contract_id,
+        counter::INCREMENT,
+        Fee::new(balance, gas_price),
+    );
+
+    assert!(matches!(result, Err(VMError::NotEnoughFunds)));
+    assert_eq!(network.balance(&payer).unwrap(), balance);
+}
+
+#[test]
+fn module_cache() {
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let counter_code = include_bytes!("contracts/counter/counter.wasm");
+    let counter_contract =
+        Contract::new(Counter::new(99), counter_code.to_vec(), &store).unwrap();
+    let counter_id = network.deploy(counter_contract).unwrap();
+
+    let fib_code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+    let fib_contract =
+        Contract::new(Fibonacci, fib_code.to_vec(), &store).unwrap();
+    let fib_id = network.deploy(fib_contract).unwrap();
+
+    // deployed modules are cached
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    for _ in 0..2 {
+        network
+            .query::<_, i32>(counter_id, counter::READ_VALUE, &mut gas)
+            .unwrap();
+    }
+
+    let stats = network.module_cache_stats();
+    assert_eq!(stats.hits, 2);
+    assert_eq!(stats.misses, 0);
+    assert_eq!(network.cached_modules(), 2);
+
+    // the least recently used module is evicted
+
+    network.set_module_cache_capacity(1);
+
+    assert_eq!(network.cached_modules(), 1);
+    assert_eq!(network.module_cache_stats().evictions, 1);
+
+    let n: u64 = 5;
+
+    for _ in 0..2 {
+        network
+            .query::<_, u64>(fib_id, (fibonacci::COMPUTE, n), &mut gas)
+            .unwrap();
+    }
+
+    let stats = network.module_cache_stats();
+    assert_eq!(stats.misses, 1);
+    assert!(stats.hits > 3);
+}
+
 #[test]
 fn delegated_call() {
     let counter = Counter::new(99);
@@ -149,276 +408,1931 @@ fn delegated_call() {
 }
 
 #[test]
-fn fibonacci() {
-    let fib = Fibonacci;
+fn read_only_query() {
+    let counter = Counter::new(99);
+    let delegator = Delegator;
 
     let store = MS::new();
 
-    let code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+    let mut network = NetworkState::<MS>::default();
 
-    let contract = Contract::new(fib, code.to_vec(), &store).unwrap();
+    let counter_code = include_bytes!("contracts/counter/counter.wasm");
+    let counter_contract =
+        Contract::new(counter, counter_code.to_vec(), &store).unwrap();
+    let counter_id = network.deploy(counter_contract).unwrap();
 
-    let mut network = NetworkState::<MS>::default();
+    let delegator_code = include_bytes!("contracts/delegator/delegator.wasm");
+    let delegator_contract =
+        Contract::new(delegator, delegator_code.to_vec(), &store).unwrap();
+    let delegator_id = network.deploy(delegator_contract).unwrap();
 
-    let contract_id = network.deploy(contract).unwrap();
+    // queries only need a shared reference to the state
+    let network = network;
 
     let mut gas = GasMeter::with_limit(1_000_000_000);
 
-    let n = 5;
+    // transacting from a query fails
 
-    for i in 0..n {
-        assert_eq!(
-            network
-                .query::<_, u64>(contract_id, (fibonacci::COMPUTE, i), &mut gas)
-                .unwrap(),
-            fibonacci_reference(i)
-        );
-    }
-}
+    let result = network.query::<_, ()>(
+        delegator_id,
+        (
+            delegator::DELEGATE_TRANSACTION_IN_QUERY,
+            counter_id,
+            counter::INCREMENT,
+        ),
+        &mut gas,
+    );
 
-#[test]
-fn stack() {
-    let stack = Stack::new();
+    assert!(matches!(result, Err(VMError::MutationInQuery)));
 
-    let store = MS::new();
+    // the value of counter is unchanged
 
-    let code = include_bytes!("contracts/stack/stack.wasm");
+    assert_eq!(
+        network
+            .query::<_, i32>(counter_id, counter::READ_VALUE, &mut gas)
+            .unwrap(),
+        99
+    );
+}
 
-    let contract = Contract::new(stack, code.to_vec(), &store).unwrap();
+#[test]
+fn gas_limited_delegated_call() {
+    let store = MS::new();
 
     let mut network = NetworkState::<MS>::default();
 
-    let contract_id = network.deploy(contract).unwrap();
+    let fib_code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+    let fib_contract =
+        Contract::new(Fibonacci, fib_code.to_vec(), &store).unwrap();
+    let fib_id = network.deploy(fib_contract).unwrap();
 
-    let mut gas = GasMeter::with_limit(1_000_000_000);
+    let delegator_code = include_bytes!("contracts/delegator/delegator.wasm");
+    let delegator_contract =
+        Contract::new(Delegator, delegator_code.to_vec(), &store).unwrap();
+    let delegator_id = network.deploy(delegator_contract).unwrap();
 
-    let n: i32 = 64;
+    let n: u64 = 5;
+    let query = Query::from_canon(&(fibonacci::COMPUTE, n), &store).unwrap();
 
-    for i in 0..n {
-        network
-            .transact::<_, ()>(contract_id, (stack::PUSH, i), &mut gas)
-            .unwrap();
-    }
+    // enough gas for the callee
 
-    for i in 0..n {
-        assert_eq!(
-            network
-                .query::<_, Option<i32>>(
-                    contract_id,
-                    (stack::PEEK, i),
-                    &mut gas
-                )
-                .unwrap(),
-            Some(i)
-        );
-    }
+    let mut gas = GasMeter::with_limit(1_000_000_000);
 
-    for i in 0..n {
-        let contract_state: Stack<MS> = network
-            .get_contract_cast_state(&contract_id)
-            .expect("A result");
+    let result = network
+        .query::<_, Option<ReturnValue>>(
+            delegator_id,
+            (
+                delegator::DELEGATE_QUERY_WITH_GAS,
+                fib_id,
+                query.clone(),
+                1_000_000u64,
+            ),
+            &mut gas,
+        )
+        .unwrap()
+        .expect("the callee to succeed");
 
-        assert_eq!(contract_state.peek(i), Some(i));
-    }
+    let value: u64 = result.cast(store.clone()).unwrap();
 
-    for i in 0..n {
-        let i = n - i - 1;
+    assert_eq!(value, 5);
 
-        assert_eq!(
-            network
-                .transact::<_, Option<i32>>(contract_id, stack::POP, &mut gas)
-                .unwrap(),
-            Some(i)
-        );
-    }
+    // the callee runs out of its budget, but the delegator does not
 
-    assert_eq!(
-        network
-            .transact::<_, Option<i32>>(contract_id, stack::POP, &mut gas)
-            .unwrap(),
-        None
-    );
-}
+    let gas_limit: u64 = 1_000;
+    let mut gas = GasMeter::with_limit(1_000_000_000);
 
-struct PoseidonModule<S> {
-    store: S,
-}
+    let result = network
+        .query::<_, Option<ReturnValue>>(
+            delegator_id,
+            (delegator::DELEGATE_QUERY_WITH_GAS, fib_id, query, gas_limit),
+            &mut gas,
+        )
+        .unwrap();
 
-impl<S> PoseidonModule<S>
-where
-    S: Store,
-{
-    fn new(store: S) -> Self {
-        PoseidonModule { store }
-    }
+    assert!(result.is_none());
+    assert!(gas.gas_left() > 0);
 }
 
-impl<S> Module for PoseidonModule<S> {
-    fn id() -> ContractId {
-        ContractId::reserved(11)
-    }
-}
+#[test]
+fn gas_limited_delegated_transaction() {
+    let store = MS::new();
 
-impl<S> HostModule<S> for PoseidonModule<S>
-where
-    S: Store,
-{
-    fn execute(&self, query: Query) -> Result<ReturnValue, S::Error> {
-        let mut source = ByteSource::new(query.as_bytes(), &self.store);
+    let mut network = NetworkState::<MS>::default();
 
-        let qid: u8 = Canon::<S>::read(&mut source)?;
+    let snapshot_code =
+        include_bytes!("contracts/self_snapshot/self_snapshot.wasm");
+    let snapshot_contract =
+        Contract::new(SelfSnapshot::new(7), snapshot_code.to_vec(), &store)
+            .unwrap();
+    let snapshot_id = network.deploy(snapshot_contract).unwrap();
 
-        match qid {
-            0 => {
-                let scalars: Vec<BlsScalar> = Canon::<S>::read(&mut source)?;
-                let ret = dusk_poseidon::sponge::hash(&scalars);
+    let delegator_code = include_bytes!("contracts/delegator/delegator.wasm");
+    let delegator_contract =
+        Contract::new(Delegator, delegator_code.to_vec(), &store).unwrap();
+    let delegator_id = network.deploy(delegator_contract).unwrap();
 
-                ReturnValue::from_canon(&ret, &self.store)
-            }
-            _ => todo!(),
-        }
-    }
+    let crossover = |network: &NetworkState<MS>| {
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        network
+            .query::<_, i32>(snapshot_id, self_snapshot::CROSSOVER, &mut gas)
+            .unwrap()
+    };
+
+    // enough gas for the callee
+
+    let transaction =
+        Transaction::from_canon(&(self_snapshot::SET_CROSSOVER, 9), &store)
+            .unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    let result = network
+        .transact::<_, Option<ReturnValue>>(
+            delegator_id,
+            (
+                delegator::DELEGATE_TRANSACTION_WITH_GAS,
+                snapshot_id,
+                transaction,
+                1_000_000u64,
+            ),
+            &mut gas,
+        )
+        .unwrap()
+        .expect("the callee to succeed");
+
+    let old: i32 = result.cast(store.clone()).unwrap();
+
+    assert_eq!(old, 7);
+    assert_eq!(crossover(&network), 9);
+
+    // the callee updates its state through a nested transaction before
+    // running out of its budget, and the update is rolled back, while the
+    // delegator does not run out of gas
+
+    let transaction =
+        Transaction::from_canon(&(self_snapshot::UPDATE_AND_LOOP, 11), &store)
+            .unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    let result = network
+        .transact::<_, Option<ReturnValue>>(
+            delegator_id,
+            (
+                delegator::DELEGATE_TRANSACTION_WITH_GAS,
+                snapshot_id,
+                transaction,
+                100_000u64,
+            ),
+            &mut gas,
+        )
+        .unwrap();
+
+    assert!(result.is_none());
+    assert!(gas.gas_left() > 0);
+    assert_eq!(crossover(&network), 9);
 }
 
 #[test]
-fn hash_as_host_fn() {
-    let test_inputs = [
-        "bb67ed265bf1db490ded2e1ede55c0d14c55521509dc73f9c354e98ab76c9625",
-        "7e74220084d75e10c89e9435d47bb5b8075991b2e29be3b84421dac3b1ee6007",
-        "5ce5481a4d78cca03498f72761da1b9f1d2aa8fb300be39f0e4fe2534f9d4308",
-    ];
+fn gas_profile() {
+    let store = MS::new();
 
-    let test_inputs: Vec<BlsScalar> = test_inputs
+    let mut network = NetworkState::<MS>::default();
+
+    let counter_code = include_bytes!("contracts/counter/counter.wasm");
+    let counter_contract =
+        Contract::new(Counter::new(99), counter_code.to_vec(), &store).unwrap();
+    let counter_id = network.deploy(counter_contract).unwrap();
+
+    let delegator_code = include_bytes!("contracts/delegator/delegator.wasm");
+    let delegator_contract =
+        Contract::new(Delegator, delegator_code.to_vec(), &store).unwrap();
+    let delegator_id = network.deploy(delegator_contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+    let mut profiler = GasProfiler::new();
+
+    network
+        .transact_profiled::<_, ()>(
+            delegator_id,
+            (
+                delegator::DELEGATE_TRANSACTION,
+                counter_id,
+                counter::INCREMENT,
+            ),
+            &mut gas,
+            &mut profiler,
+        )
+        .unwrap();
+
+    // every unit of gas spent is accounted for
+
+    assert_eq!(profiler.total(), gas.spent());
+
+    let delegator_ids = profiler
+        .functions()
         .iter()
-        .map(|input| BlsScalar::from_hex_str(input).unwrap())
-        .collect();
+        .filter(|((contract, _), _)| *contract == delegator_id)
+        .count();
+    let counter_ids = profiler
+        .functions()
+        .iter()
+        .filter(|((contract, _), _)| *contract == counter_id)
+        .count();
 
-    let hash = HostFnTest::new();
+    assert!(delegator_ids > 0);
+    assert!(counter_ids > 0);
 
-    let store = MS::new();
+    // the counter is profiled as called by the delegator
 
-    let code = include_bytes!("contracts/host_fn/host_fn.wasm");
+    let folded = profiler.folded();
+    let counter_name = profiler.frame_name(&(counter_id, None));
+    let counter_prefix = counter_name.split("::").next().unwrap();
 
-    let contract = Contract::new(hash, code.to_vec(), &store).unwrap();
+    assert!(folded
+        .lines()
+        .any(|line| line.contains(&format!(";{}::", counter_prefix))));
 
-    let mut network = NetworkState::<MS>::default();
+    // gas spent by a gas-limited call is only accounted for once
 
-    let pos_mod = PoseidonModule::new(store.clone());
+    let transaction =
+        Transaction::from_canon(&counter::INCREMENT, &store).unwrap();
 
-    network.register_host_module(pos_mod);
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+    let mut profiler = GasProfiler::new();
+
+    network
+        .transact_profiled::<_, Option<ReturnValue>>(
+            delegator_id,
+            (
+                delegator::DELEGATE_TRANSACTION_WITH_GAS,
+                counter_id,
+                transaction,
+                1_000_000u64,
+            ),
+            &mut gas,
+            &mut profiler,
+        )
+        .unwrap()
+        .expect("the counter to succeed");
+
+    assert_eq!(profiler.total(), gas.spent());
+}
+
+#[test]
+fn fibonacci() {
+    let fib = Fibonacci;
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+
+    let contract = Contract::new(fib, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
 
     let contract_id = network.deploy(contract).unwrap();
 
     let mut gas = GasMeter::with_limit(1_000_000_000);
 
-    assert_eq!(
-        "0xe36f4ea9b858d5c85b02770823c7c5d8253c28787d17f283ca348b906dca8528",
-        format!(
-            "{:#x}",
+    let n = 5;
+
+    for i in 0..n {
+        assert_eq!(
             network
-                .query::<_, BlsScalar>(
-                    contract_id,
-                    (host_fn::HASH, test_inputs),
-                    &mut gas
-                )
-                .unwrap()
-        )
-    );
+                .query::<_, u64>(contract_id, (fibonacci::COMPUTE, i), &mut gas)
+                .unwrap(),
+            fibonacci_reference(i)
+        );
+    }
 }
 
 #[test]
-fn block_height() {
-    let bh = BlockHeight::new();
+fn gas_is_metered() {
+    let counter = Counter::new(99);
 
     let store = MS::new();
 
-    let code = include_bytes!("contracts/block_height/block_height.wasm");
+    let code = include_bytes!("contracts/counter/counter.wasm");
 
-    let contract = Contract::new(bh, code.to_vec(), &store).unwrap();
+    let contract = Contract::new(counter, code.to_vec(), &store).unwrap();
 
-    let mut network = NetworkState::<MS>::with_block_height(99);
+    let mut network = NetworkState::<MS>::default();
 
     let contract_id = network.deploy(contract).unwrap();
 
     let mut gas = GasMeter::with_limit(1_000_000_000);
 
-    assert_eq!(
-        99,
-        network
-            .query::<_, u64>(contract_id, block_height::BLOCK_HEIGHT, &mut gas)
-            .unwrap()
-    )
+    network
+        .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+        .unwrap();
+
+    assert!(gas.spent() > 0);
 }
 
 #[test]
-fn self_snapshot() {
-    let bh = SelfSnapshot::new(7);
+fn out_of_gas() {
+    let fib = Fibonacci;
 
     let store = MS::new();
 
-    let code = include_bytes!("contracts/self_snapshot/self_snapshot.wasm");
+    let code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
 
-    let contract = Contract::new(bh, code.to_vec(), &store).unwrap();
+    let contract = Contract::new(fib, code.to_vec(), &store).unwrap();
 
-    let mut network = NetworkState::<MS>::with_block_height(99);
+    let mut network = NetworkState::<MS>::default();
 
     let contract_id = network.deploy(contract).unwrap();
 
-    let mut gas = GasMeter::with_limit(1_000_000_000);
+    let mut gas = GasMeter::with_limit(1_000);
 
-    assert_eq!(
-        7,
-        network
-            .query::<_, i32>(contract_id, self_snapshot::CROSSOVER, &mut gas)
-            .unwrap()
+    let n: u64 = 20;
+
+    let result =
+        network.query::<_, u64>(contract_id, (fibonacci::COMPUTE, n), &mut gas);
+
+    assert!(matches!(result, Err(VMError::OutOfGas)));
+    assert_eq!(gas.gas_left(), 0);
+}
+
+#[test]
+fn call_base_cost() {
+    let counter = Counter::new(99);
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+
+    let contract = Contract::new(counter, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let schedule = Schedule::default();
+
+    // the state and the argument are copied to the memory of the contract
+    let copied = Canon::<MS>::encoded_len(&Counter::new(99))
+        + Canon::<MS>::encoded_len(&counter::READ_VALUE);
+
+    let mut gas = GasMeter::with_limit(
+        schedule.call_base_cost
+            + schedule.instantiate_base_cost
+            + copied as u64 * schedule.sandbox_data_write_cost
+            - 1,
     );
 
-    // returns old value
-    assert_eq!(
-        network
-            .transact::<_, i32>(
-                contract_id,
-                (self_snapshot::SET_CROSSOVER, 9),
-                &mut gas,
-            )
-            .unwrap(),
-        7
+    let stats = network.module_cache_stats();
+
+    let result =
+        network.query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas);
+
+    assert!(matches!(result, Err(VMError::OutOfGas)));
+
+    // the call is rejected before the module is even looked up
+    assert_eq!(network.module_cache_stats(), stats);
+}
+
+#[test]
+fn stack_overflow() {
+    let fib = Fibonacci;
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+
+    let contract = Contract::new(fib, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    network.add_schedule(
+        0,
+        Schedule {
+            max_stack_height: 1024,
+            ..Schedule::default()
+        },
     );
 
-    assert_eq!(
-        9,
-        network
-            .query::<_, i32>(contract_id, self_snapshot::CROSSOVER, &mut gas)
-            .unwrap()
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    let n: u64 = 1_000;
+
+    let result =
+        network.query::<_, u64>(contract_id, (fibonacci::COMPUTE, n), &mut gas);
+
+    assert!(matches!(result, Err(VMError::StackOverflow)));
+}
+
+#[test]
+fn call_depth_limit() {
+    let fib = Fibonacci;
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+
+    let contract = Contract::new(fib, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    network.add_schedule(
+        0,
+        Schedule {
+            max_call_depth: 4,
+            ..Schedule::default()
+        },
     );
 
-    network
-        .transact::<_, ()>(
-            contract_id,
-            (self_snapshot::SELF_CALL_TEST_A, 10),
-            &mut gas,
-        )
-        .unwrap();
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    // computing fibonacci(n) nests n calls
 
     assert_eq!(
-        10,
         network
-            .query::<_, i32>(contract_id, self_snapshot::CROSSOVER, &mut gas)
-            .unwrap()
+            .query::<_, u64>(contract_id, (fibonacci::COMPUTE, 4u64), &mut gas)
+            .unwrap(),
+        3
     );
 
-    let result = network.transact::<_, ()>(
+    let result = network.query::<_, u64>(
         contract_id,
-        (self_snapshot::UPDATE_AND_PANIC, 11),
+        (fibonacci::COMPUTE, 5u64),
         &mut gas,
     );
 
-    assert!(result.is_err());
+    assert!(matches!(result, Err(VMError::CallDepthExceeded)));
+}
+
+#[test]
+fn memory_limit() {
+    let counter = Counter::new(99);
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+
+    let contract = Contract::new(counter, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    network.add_schedule(
+        0,
+        Schedule {
+            max_memory_pages: 1,
+            ..Schedule::default()
+        },
+    );
+
+    let result = network.deploy(contract);
+
+    assert!(matches!(
+        result,
+        Err(VMError::InvalidWASMModule(
+            InvalidModule::TooManyMemoryPages { .. }
+        ))
+    ));
+}
+
+// Grows its memory by the number of pages given as argument to its query,
+// returning nothing
+const MEMORY_GROW: &str = r#"
+    (module
+      (memory (export "memory") 1)
+      (func (export "q") (param $buffer i32)
+        (drop (memory.grow (i32.load offset=8 (local.get $buffer))))
+        (i32.store (local.get $buffer) (i32.const 0))
+        (i32.store offset=4 (local.get $buffer) (i32.const 0)))
+      (func (export "t") (param i32)
+        unreachable))
+"#;
+
+#[test]
+fn memory_grow() {
+    let store = MS::new();
+
+    let code = wat::parse_str(MEMORY_GROW).unwrap();
+
+    let contract = Contract::new((), code, &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    network.add_schedule(
+        0,
+        Schedule {
+            grow_mem_cost: 1000,
+            max_memory_pages: 4,
+            ..Schedule::default()
+        },
+    );
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let grow = |pages: u32| {
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        network
+            .query::<_, ()>(contract_id, pages, &mut gas)
+            .map(|_| gas.spent())
+    };
+
+    // every page added is charged for
+    let spent = grow(0).unwrap();
+    assert_eq!(grow(2).unwrap(), spent + 2 * 1000);
+
+    // the memory holds one page and the call buffer, and cannot grow beyond
+    // four pages
+    assert!(matches!(grow(3), Err(VMError::MemoryLimitExceeded)));
+}
+
+#[test]
+fn module_validation() {
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let mut deploy = |code: &[u8]| {
+        let contract =
+            Contract::new(Counter::new(0), code.to_vec(), &store).unwrap();
+        match network.deploy(contract) {
+            Err(VMError::InvalidWASMModule(reason)) => reason,
+            _ => panic!("the module to be rejected"),
+        }
+    };
+
+    // not wasm at all
+
+    assert_eq!(deploy(&[0xde, 0xad, 0xbe, 0xef]), InvalidModule::Malformed);
+
+    let mut deploy_wat = |wat: &str| deploy(&wat::parse_str(wat).unwrap());
+
+    // no memory
+
+    assert_eq!(
+        deploy_wat("(module)"),
+        InvalidModule::MissingExport("memory")
+    );
+
+    // no entrypoint
+
+    assert_eq!(
+        deploy_wat(r#"(module (memory (export "memory") 1))"#),
+        InvalidModule::MissingExport("q")
+    );
+
+    // only one of the entrypoints
+
+    assert_eq!(
+        deploy_wat(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "q") (param i32)))
+            "#
+        ),
+        InvalidModule::MissingExport("t")
+    );
+
+    // an export reserved to the instrumentation
+
+    assert_eq!(
+        deploy_wat(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "q") (param i32))
+              (func (export "t") (param i32))
+              (func (export "__stack_height_get") (result i32)
+                (i32.const 0)))
+            "#
+        ),
+        InvalidModule::ReservedExport("__stack_height_get".into())
+    );
+    assert_eq!(
+        deploy_wat(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "q") (param i32))
+              (func (export "t") (param i32))
+              (func (export "__memory_limit_exceeded") (result i32)
+                (i32.const 0)))
+            "#
+        ),
+        InvalidModule::ReservedExport("__memory_limit_exceeded".into())
+    );
+
+    // a start function
+
+    assert_eq!(
+        deploy_wat(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func $start)
+              (start $start)
+              (func (export "q") (param i32))
+              (func (export "t") (param i32)))
+            "#
+        ),
+        InvalidModule::StartFunction
+    );
+
+    // unknown import
+
+    assert_eq!(
+        deploy_wat(r#"(module (import "env" "foo" (func)))"#),
+        InvalidModule::UnknownImport {
+            module: "env".into(),
+            field: "foo".into(),
+        }
+    );
+
+    // known import with the wrong signature
+
+    assert_eq!(
+        deploy_wat(
+            r#"
+            (module
+              (import "env" "debug" (func (param i32)))
+              (memory (export "memory") 1)
+              (func (export "q") (param i32))
+              (func (export "t") (param i32)))
+            "#
+        ),
+        InvalidModule::ImportSignature {
+            module: "env".into(),
+            field: "debug".into(),
+        }
+    );
+
+    // too large
+
+    assert!(matches!(
+        deploy(&vec![0; Schedule::default().max_code_size as usize + 1]),
+        InvalidModule::CodeTooLarge { .. }
+    ));
+}
+
+#[test]
+fn float_instructions() {
+    let store = MS::new();
+
+    let code = wat::parse_str(
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "q") (param i32)
+            (drop (f32.add (f32.const 0) (f32.const 0))))
+          (func (export "t") (param i32)
+            unreachable))
+        "#,
+    )
+    .unwrap();
+
+    let contract =
+        || Contract::new(Counter::new(0), code.to_vec(), &store).unwrap();
+
+    // rejected by default
+
+    let mut network = NetworkState::<MS>::default();
+
+    assert!(matches!(
+        network.deploy(contract()),
+        Err(VMError::FloatInstruction(_))
+    ));
+
+    // allowed by the schedule
+
+    network.add_schedule(
+        0,
+        Schedule {
+            version: 1,
+            allow_floats: true,
+            ..Schedule::default()
+        },
+    );
+
+    assert!(network.deploy(contract()).is_ok());
+
+    // float types are rejected even without float instructions
+
+    let mut network = NetworkState::<MS>::default();
+
+    for module in &[
+        r#"(func $sig (param f32))"#,
+        r#"(func $local (local f64))"#,
+        r#"(global $global (mut f64) (f64.const 0))"#,
+    ] {
+        let code = wat::parse_str(format!(
+            r#"
+            (module
+              {}
+              (memory (export "memory") 1)
+              (func (export "q") (param i32))
+              (func (export "t") (param i32)))
+            "#,
+            module
+        ))
+        .unwrap();
+        let contract = Contract::new(Counter::new(0), code, &store).unwrap();
+
+        assert!(matches!(
+            network.deploy(contract),
+            Err(VMError::FloatInstruction(_))
+        ));
+    }
+}
+
+#[test]
+fn large_argument() {
+    let counter = Counter::new(99);
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+
+    let contract = Contract::new(counter, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    // the memory is grown to fit arguments spanning many pages
+
+    let padding = vec![0xffu8; 2 * 1024 * 1024];
+
+    assert_eq!(
+        network
+            .query::<_, i32>(
+                contract_id,
+                (counter::READ_VALUE, padding),
+                &mut gas
+            )
+            .unwrap(),
+        99
+    );
+
+    // but not beyond the memory limit
+
+    let max_len = Schedule::default().max_memory_pages as usize * 64 * 1024;
+    let padding = vec![0xffu8; max_len];
+
+    let result = network.query::<_, i32>(
+        contract_id,
+        (counter::READ_VALUE, padding),
+        &mut gas,
+    );
+
+    assert!(matches!(result, Err(VMError::CallBufferTooLarge(_))));
+}
+
+#[test]
+fn memory_access_out_of_bounds() {
+    let store = MS::new();
+
+    // debugs 16 bytes at an offset past the end of the memory
+    let code = wat::parse_str(
+        r#"
+        (module
+          (import "env" "debug" (func $debug (param i32 i32)))
+          (memory (export "memory") 1)
+          (func (export "q") (param i32)
+            (call $debug (i32.const -1) (i32.const 16)))
+          (func (export "t") (param i32)
+            unreachable))
+        "#,
+    )
+    .unwrap();
+
+    let contract = Contract::new(Counter::new(0), code, &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    let result = network.query::<_, u8>(contract_id, 0u8, &mut gas);
+
+    assert!(matches!(result, Err(VMError::MemoryAccessOutOfBounds)));
+}
+
+#[test]
+fn schedule_upgrade() {
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+
+    let expensive = Schedule {
+        version: 1,
+        regular_op_cost: 10,
+        ..Schedule::default()
+    };
+
+    let spent: Vec<_> = [9, 10]
+        .iter()
+        .map(|height| {
+            let counter = Counter::new(99);
+            let contract =
+                Contract::new(counter, code.to_vec(), &store).unwrap();
+
+            let mut network = NetworkState::<MS>::with_block_height(*height);
+
+            network.add_schedule(10, expensive.clone());
+
+            let contract_id = network.deploy(contract).unwrap();
+
+            let mut gas = GasMeter::with_limit(1_000_000_000);
+
+            network
+                .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+                .unwrap();
+
+            gas.spent()
+        })
+        .collect();
+
+    assert!(spent[1] > spent[0]);
+}
+
+#[test]
+fn schedule_reused_version() {
+    let counter = Counter::new(99);
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+
+    let contract = Contract::new(counter, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let spent = |network: &NetworkState<MS>| {
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+
+        network
+            .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+            .unwrap();
+
+        gas.spent()
+    };
+
+    let cheap = spent(&network);
+
+    // same version as the default schedule, with different costs
+    network.add_schedule(
+        0,
+        Schedule {
+            regular_op_cost: 10,
+            ..Schedule::default()
+        },
+    );
+
+    assert!(spent(&network) > cheap);
+}
+
+#[test]
+fn schedule_persistence() {
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let schedule = Schedule {
+        version: 1,
+        ..Schedule::default()
+    };
+
+    network.add_schedule(10, schedule.clone());
+
+    let id = store.put(&network).unwrap();
+    let restored: NetworkState<MS> = store.get(&id).unwrap();
+
+    assert!(restored.schedule_at(9) == &Schedule::default());
+    assert!(restored.schedule_at(10) == &schedule);
+}
+
+#[test]
+fn stack() {
+    let stack = Stack::new();
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/stack/stack.wasm");
+
+    let contract = Contract::new(stack, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    let n: i32 = 64;
+
+    for i in 0..n {
+        network
+            .transact::<_, ()>(contract_id, (stack::PUSH, i), &mut gas)
+            .unwrap();
+    }
+
+    for i in 0..n {
+        assert_eq!(
+            network
+                .query::<_, Option<i32>>(
+                    contract_id,
+                    (stack::PEEK, i),
+                    &mut gas
+                )
+                .unwrap(),
+            Some(i)
+        );
+    }
+
+    for i in 0..n {
+        let contract_state: Stack<MS> = network
+            .get_contract_cast_state(&contract_id)
+            .expect("A result");
+
+        assert_eq!(contract_state.peek(i), Some(i));
+    }
+
+    for i in 0..n {
+        let i = n - i - 1;
+
+        assert_eq!(
+            network
+                .transact::<_, Option<i32>>(contract_id, stack::POP, &mut gas)
+                .unwrap(),
+            Some(i)
+        );
+    }
+
+    assert_eq!(
+        network
+            .transact::<_, Option<i32>>(contract_id, stack::POP, &mut gas)
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn store_cost() {
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/stack/stack.wasm");
+
+    let expensive = Schedule {
+        version: 1,
+        store_base_cost: 1_000_000,
+        ..Schedule::default()
+    };
+
+    let spent: Vec<_> = [Schedule::default(), expensive]
+        .iter()
+        .map(|schedule| {
+            let stack = Stack::new();
+            let contract = Contract::new(stack, code.to_vec(), &store).unwrap();
+
+            let mut network = NetworkState::<MS>::default();
+
+            network.add_schedule(0, schedule.clone());
+
+            let contract_id = network.deploy(contract).unwrap();
+
+            let mut gas = GasMeter::with_limit(1_000_000_000_000);
+
+            for i in 0..64 {
+                network
+                    .transact::<_, ()>(contract_id, (stack::PUSH, i), &mut gas)
+                    .unwrap();
+            }
+
+            gas.spent()
+        })
+        .collect();
+
+    assert!(spent[1] >= spent[0] + 1_000_000);
+}
+
+#[test]
+fn store_value_limit() {
+    let stack = Stack::new();
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/stack/stack.wasm");
+
+    let contract = Contract::new(stack, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    for i in 0..64 {
+        network
+            .transact::<_, ()>(contract_id, (stack::PUSH, i), &mut gas)
+            .unwrap();
+    }
+
+    network.add_schedule(
+        0,
+        Schedule {
+            max_store_value_len: 1,
+            ..Schedule::default()
+        },
+    );
+
+    let result = network.query::<_, Option<i32>>(
+        contract_id,
+        (stack::PEEK, 0),
+        &mut gas,
+    );
+
+    assert!(matches!(result, Err(VMError::StoreValueTooLarge)));
+}
+
+struct PoseidonModule<S> {
+    store: S,
+}
+
+impl<S> PoseidonModule<S>
+where
+    S: Store,
+{
+    fn new(store: S) -> Self {
+        PoseidonModule { store }
+    }
+}
+
+impl<S> Module for PoseidonModule<S> {
+    fn id() -> ContractId {
+        ContractId::reserved(11)
+    }
+}
+
+impl<S> HostModule<S> for PoseidonModule<S>
+where
+    S: Store,
+{
+    fn execute(&self, query: Query) -> Result<ReturnValue, S::Error> {
+        let mut source = ByteSource::new(query.as_bytes(), &self.store);
+
+        let qid: u8 = Canon::<S>::read(&mut source)?;
+
+        match qid {
+            0 => {
+                let scalars: Vec<BlsScalar> = Canon::<S>::read(&mut source)?;
+                let ret = dusk_poseidon::sponge::hash(&scalars);
+
+                ReturnValue::from_canon(&ret, &self.store)
+            }
+            _ => todo!(),
+        }
+    }
+}
+
+#[test]
+fn hash_as_host_fn() {
+    let test_inputs = [
+        "bb67ed265bf1db490ded2e1ede55c0d14c55521509dc73f9c354e98ab76c9625",
+        "7e74220084d75e10c89e9435d47bb5b8075991b2e29be3b84421dac3b1ee6007",
+        "5ce5481a4d78cca03498f72761da1b9f1d2aa8fb300be39f0e4fe2534f9d4308",
+    ];
+
+    let test_inputs: Vec<BlsScalar> = test_inputs
+        .iter()
+        .map(|input| BlsScalar::from_hex_str(input).unwrap())
+        .collect();
+
+    let hash = HostFnTest::new();
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/host_fn/host_fn.wasm");
+
+    let contract = Contract::new(hash, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let pos_mod = PoseidonModule::new(store.clone());
+
+    network.register_host_module(pos_mod);
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    assert_eq!(
+        "0xe36f4ea9b858d5c85b02770823c7c5d8253c28787d17f283ca348b906dca8528",
+        format!(
+            "{:#x}",
+            network
+                .query::<_, BlsScalar>(
+                    contract_id,
+                    (host_fn::HASH, test_inputs),
+                    &mut gas
+                )
+                .unwrap()
+        )
+    );
+}
+
+#[test]
+fn block_height() {
+    let bh = BlockHeight::new();
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/block_height/block_height.wasm");
+
+    let contract = Contract::new(bh, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::with_block_height(99);
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    assert_eq!(
+        99,
+        network
+            .query::<_, u64>(contract_id, block_height::BLOCK_HEIGHT, &mut gas)
+            .unwrap()
+    )
+}
+
+#[test]
+fn self_snapshot() {
+    let bh = SelfSnapshot::new(7);
+
+    let store = MS::new();
+
+    let code = include_bytes!("contracts/self_snapshot/self_snapshot.wasm");
+
+    let contract = Contract::new(bh, code.to_vec(), &store).unwrap();
+
+    let mut network = NetworkState::<MS>::with_block_height(99);
+
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    assert_eq!(
+        7,
+        network
+            .query::<_, i32>(contract_id, self_snapshot::CROSSOVER, &mut gas)
+            .unwrap()
+    );
+
+    // returns old value
+    assert_eq!(
+        network
+            .transact::<_, i32>(
+                contract_id,
+                (self_snapshot::SET_CROSSOVER, 9),
+                &mut gas,
+            )
+            .unwrap(),
+        7
+    );
+
+    assert_eq!(
+        9,
+        network
+            .query::<_, i32>(contract_id, self_snapshot::CROSSOVER, &mut gas)
+            .unwrap()
+    );
+
+    network
+        .transact::<_, ()>(
+            contract_id,
+            (self_snapshot::SELF_CALL_TEST_A, 10),
+            &mut gas,
+        )
+        .unwrap();
+
+    assert_eq!(
+        10,
+        network
+            .query::<_, i32>(contract_id, self_snapshot::CROSSOVER, &mut gas)
+            .unwrap()
+    );
+
+    let result = network.transact::<_, ()>(
+        contract_id,
+        (self_snapshot::UPDATE_AND_PANIC, 11),
+        &mut gas,
+    );
+
+    assert!(result.is_err());
+
+    assert_eq!(
+        10,
+        network
+            .query::<_, i32>(contract_id, self_snapshot::CROSSOVER, &mut gas)
+            .unwrap()
+    );
+}
+
+#[test]
+fn reentrancy_policy() {
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::default();
+
+    let fib_code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+    let fib_contract =
+        Contract::new(Fibonacci, fib_code.to_vec(), &store).unwrap();
+    let fib_id = network.deploy(fib_contract).unwrap();
+
+    let snapshot_code =
+        include_bytes!("contracts/self_snapshot/self_snapshot.wasm");
+    let snapshot_contract =
+        Contract::new(SelfSnapshot::new(7), snapshot_code.to_vec(), &store)
+            .unwrap();
+    let snapshot_id = network.deploy(snapshot_contract).unwrap();
+
+    let delegator_code = include_bytes!("contracts/delegator/delegator.wasm");
+    let delegator_contract =
+        Contract::new(Delegator, delegator_code.to_vec(), &store).unwrap();
+    let delegator_id = network.deploy(delegator_contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    // re-entrancy is allowed by default, and visible to contracts
+
+    assert_eq!(
+        network
+            .query::<_, u64>(fib_id, (fibonacci::COMPUTE, 5u64), &mut gas)
+            .unwrap(),
+        5
+    );
+
+    assert!(!network
+        .query::<_, bool>(snapshot_id, self_snapshot::REENTERED, &mut gas)
+        .unwrap());
+
+    assert!(network
+        .query::<_, bool>(snapshot_id, self_snapshot::SELF_REENTERED, &mut gas)
+        .unwrap());
+
+    assert!(network
+        .query::<_, bool>(
+            snapshot_id,
+            (self_snapshot::DELEGATED_REENTERED, delegator_id),
+            &mut gas
+        )
+        .unwrap());
+
+    // self-calls only
+
+    network.set_reentrancy_policy(ReentrancyPolicy::SelfCalls);
 
     assert_eq!(
-        10,
         network
-            .query::<_, i32>(contract_id, self_snapshot::CROSSOVER, &mut gas)
+            .query::<_, u64>(fib_id, (fibonacci::COMPUTE, 5u64), &mut gas)
+            .unwrap(),
+        5
+    );
+
+    network
+        .transact::<_, ()>(
+            snapshot_id,
+            (self_snapshot::SELF_CALL_TEST_A, 10),
+            &mut gas,
+        )
+        .unwrap();
+
+    // re-entering through another contract is not a self-call
+
+    let result = network.query::<_, bool>(
+        snapshot_id,
+        (self_snapshot::DELEGATED_REENTERED, delegator_id),
+        &mut gas,
+    );
+
+    assert!(matches!(result, Err(VMError::Reentrancy)));
+
+    // forbidden
+
+    network.set_reentrancy_policy(ReentrancyPolicy::Forbid);
+
+    assert_eq!(
+        network
+            .query::<_, u64>(fib_id, (fibonacci::COMPUTE, 1u64), &mut gas)
+            .unwrap(),
+        1
+    );
+
+    let result =
+        network.query::<_, u64>(fib_id, (fibonacci::COMPUTE, 5u64), &mut gas);
+
+    assert!(matches!(result, Err(VMError::Reentrancy)));
+
+    let result = network.transact::<_, ()>(
+        snapshot_id,
+        (self_snapshot::SELF_CALL_TEST_A, 11),
+        &mut gas,
+    );
+
+    assert!(matches!(result, Err(VMError::Reentrancy)));
+
+    // a gas-limited call cannot recover from a forbidden re-entry
+
+    let query = Query::from_canon(&(fibonacci::COMPUTE, 1u64), &store).unwrap();
+    let result = network.query::<_, Option<ReturnValue>>(
+        delegator_id,
+        (
+            delegator::DELEGATE_QUERY_WITH_GAS,
+            delegator_id,
+            query,
+            1_000_000u64,
+        ),
+        &mut gas,
+    );
+
+    assert!(matches!(result, Err(VMError::Reentrancy)));
+
+    assert_eq!(
+        network
+            .query::<_, i32>(snapshot_id, self_snapshot::CROSSOVER, &mut gas)
+            .unwrap(),
+        10
+    );
+
+    // unknown policies are not decoded
+
+    let mut source = ByteSource::new(&[3u8], &store);
+    assert!(<ReentrancyPolicy as Canon<MS>>::read(&mut source).is_err());
+}
+
+#[test]
+fn persistence() {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    let dir = std::env::temp_dir()
+        .join(format!("rusk-vm-persistence-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+
+    let contract_id = {
+        let store = DiskStore::open(&dir).unwrap();
+
+        let contract =
+            Contract::new(Counter::new(99), code.to_vec(), &store).unwrap();
+
+        let mut network = NetworkState::with_store(store.clone());
+        let contract_id = network.deploy(contract).unwrap();
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        network
+            .transact::<_, ()>(contract_id, counter::INCREMENT, &mut gas)
+            .unwrap();
+
+        network.commit_root().unwrap();
+
+        contract_id
+    };
+
+    // a record torn by a crash is discarded on opening
+
+    OpenOptions::new()
+        .append(true)
+        .open(dir.join("values.log"))
+        .unwrap()
+        .write_all(&[0xff; 40])
+        .unwrap();
+
+    let store = DiskStore::open(&dir).unwrap();
+    let network = NetworkState::restore(store)
+        .unwrap()
+        .expect("a state was committed");
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    assert_eq!(
+        network
+            .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+            .unwrap(),
+        100
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disk_store_recovery() {
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    let dir = std::env::temp_dir()
+        .join(format!("rusk-vm-recovery-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    {
+        let store = DiskStore::open(&dir).unwrap();
+
+        // the directory is held by the open store
+        assert!(matches!(DiskStore::open(&dir), Err(DiskError::Locked)));
+
+        let value = b"committed value";
+        let id = store.put_raw(value).unwrap();
+        store.set_root(&id).unwrap();
+
+        // values are read back from the log
+        let mut bytes = [0u8; 15];
+        store.fetch(&id, &mut bytes).unwrap();
+        assert_eq!(&bytes, value);
+    }
+
+    // and released once it is dropped
+
+    drop(DiskStore::open(&dir).unwrap());
+
+    // a record committed by the root cannot be discarded as torn
+
+    let mut log = OpenOptions::new()
+        .write(true)
+        .open(dir.join("values.log"))
+        .unwrap();
+    // past the header of the first record, into its value
+    log.seek(SeekFrom::Start(40)).unwrap();
+    log.write_all(&[0]).unwrap();
+    drop(log);
+
+    assert!(matches!(DiskStore::open(&dir), Err(DiskError::Corrupted)));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn state_root() {
+    let code = include_bytes!("contracts/counter/counter.wasm");
+
+    // runs the same history against a fresh state, recording the root after
+    // every step
+    let history = || {
+        let store = MS::new();
+
+        let mut network = NetworkState::<MS>::with_store(store.clone());
+        let mut roots = vec![network.root().unwrap()];
+
+        let contract =
+            Contract::new(Counter::new(99), code.to_vec(), &store).unwrap();
+        let contract_id = network.deploy(contract).unwrap();
+        roots.push(network.root().unwrap());
+
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        network
+            .transact::<_, ()>(contract_id, counter::INCREMENT, &mut gas)
+            .unwrap();
+        roots.push(network.root().unwrap());
+
+        network.deposit(contract_id, 1_000).unwrap();
+        roots.push(network.root().unwrap());
+
+        network.set_reentrancy_policy(ReentrancyPolicy::Forbid);
+        roots.push(network.root().unwrap());
+
+        network.add_schedule(
+            10,
+            Schedule {
+                version: 1,
+                ..Schedule::default()
+            },
+        );
+        roots.push(network.root().unwrap());
+
+        // queries leave the state untouched
+        network
+            .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+            .unwrap();
+        assert_eq!(network.root().unwrap(), roots[roots.len() - 1]);
+
+        // the root survives a round trip through the store
+        let root = network.commit().unwrap();
+        let loaded = NetworkState::<MS>::load(store, &root).unwrap();
+        assert_eq!(loaded.root().unwrap(), roots[roots.len() - 1]);
+
+        roots
+    };
+
+    let roots = history();
+
+    // every change alters the root
+    for (i, root) in roots.iter().enumerate() {
+        assert!(!roots[..i].contains(root));
+    }
+
+    // identical histories give identical roots
+    assert_eq!(roots, history());
+
+    // the block height is committed to
+    assert_ne!(
+        NetworkState::<MS>::with_block_height(1).root().unwrap(),
+        roots[0]
+    );
+
+    // the root does not depend on the order contracts and balances were
+    // added in
+    let fib_code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+    let ordered = |reversed: bool| {
+        let store = MS::new();
+
+        let mut network = NetworkState::<MS>::with_store(store.clone());
+
+        let mut contracts = vec![
+            Contract::new(Counter::new(99), code.to_vec(), &store).unwrap(),
+            Contract::new(Fibonacci, fib_code.to_vec(), &store).unwrap(),
+        ];
+        if reversed {
+            contracts.reverse();
+        }
+
+        for contract in contracts {
+            let id = network.deploy(contract).unwrap();
+            network.deposit(id, 1_000).unwrap();
+        }
+
+        network.root().unwrap()
+    };
+
+    assert_eq!(ordered(false), ordered(true));
+}
+
+#[test]
+fn contract_proofs() {
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::with_store(store.clone());
+
+    let absent = [
+        ContractId::reserved(1),
+        ContractId::from(&[0x80; 32][..]),
+        ContractId::from(&[0xff; 32][..]),
+    ];
+
+    // absence can be proven in an empty state
+    let root = network.root().unwrap();
+    for id in absent.iter() {
+        let proof = network.prove(id).unwrap();
+        assert!(proof.contract().is_none());
+        assert!(verify_proof(&root, id, &proof));
+    }
+
+    let counter_code = include_bytes!("contracts/counter/counter.wasm");
+    let fib_code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+    let stack_code = include_bytes!("contracts/stack/stack.wasm");
+
+    let counter_id = network
+        .deploy(
+            Contract::new(Counter::new(99), counter_code.to_vec(), &store)
+                .unwrap(),
+        )
+        .unwrap();
+    let fib_id = network
+        .deploy(Contract::new(Fibonacci, fib_code.to_vec(), &store).unwrap())
+        .unwrap();
+    network
+        .deploy(
+            Contract::new(Stack::<MS>::new(), stack_code.to_vec(), &store)
+                .unwrap(),
+        )
+        .unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+    network
+        .transact::<_, ()>(counter_id, counter::INCREMENT, &mut gas)
+        .unwrap();
+
+    let root = network.root().unwrap();
+
+    // inclusion
+
+    let proof = network.prove(&counter_id).unwrap();
+    assert!(verify_proof(&root, &counter_id, &proof));
+
+    let (code_hash, state) = proof.contract().expect("counter is deployed");
+    assert_eq!(code_hash.as_ref(), counter_id.as_bytes());
+    assert_eq!(
+        state.as_bytes(),
+        network
+            .get_contract(&counter_id)
             .unwrap()
+            .state()
+            .as_bytes()
     );
+
+    // the proof does not hold for another contract or another root
+
+    assert!(!verify_proof(&root, &fib_id, &proof));
+    assert!(!verify_proof(
+        &NetworkState::<MS>::default().root().unwrap(),
+        &counter_id,
+        &proof
+    ));
+
+    // nor once the state of the contract changed
+
+    network
+        .transact::<_, ()>(counter_id, counter::INCREMENT, &mut gas)
+        .unwrap();
+    assert!(!verify_proof(&network.root().unwrap(), &counter_id, &proof));
+
+    // absence
+
+    let root = network.root().unwrap();
+    for id in absent.iter() {
+        let proof = network.prove(id).unwrap();
+        assert!(proof.contract().is_none());
+        assert!(verify_proof(&root, id, &proof));
+
+        // the proof of absence of a contract cannot deny a deployed one
+        assert!(!verify_proof(&root, &fib_id, &proof));
+    }
+}
+
+#[test]
+fn contract_proof_tampering() {
+    type Proof = ContractProof<<MS as Store>::Ident>;
+
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::with_store(store.clone());
+
+    let counter_code = include_bytes!("contracts/counter/counter.wasm");
+    let fib_code = include_bytes!("contracts/fibonacci/fibonacci.wasm");
+    let stack_code = include_bytes!("contracts/stack/stack.wasm");
+
+    let counter_id = network
+        .deploy(
+            Contract::new(Counter::new(99), counter_code.to_vec(), &store)
+                .unwrap(),
+        )
+        .unwrap();
+    network
+        .deploy(Contract::new(Fibonacci, fib_code.to_vec(), &store).unwrap())
+        .unwrap();
+    network
+        .deploy(
+            Contract::new(Stack::<MS>::new(), stack_code.to_vec(), &store)
+                .unwrap(),
+        )
+        .unwrap();
+
+    let root = network.root().unwrap();
+
+    let decode = |bytes: &[u8]| -> Option<Proof> {
+        Canon::<MS>::read(&mut ByteSource::new(bytes, &store)).ok()
+    };
+
+    let proof = network.prove(&counter_id).unwrap();
+    let len = Canon::<MS>::encoded_len(&proof);
+    let mut bytes = vec![0; len];
+    Canon::<MS>::write(&proof, &mut ByteSink::new(&mut bytes[..], &store))
+        .unwrap();
+
+    // the proof survives a round trip through its encoding
+    let decoded = decode(&bytes).expect("the proof decodes");
+    assert!(verify_proof(&root, &counter_id, &decoded));
+
+    let ident_len = <MS as Store>::Ident::default().as_ref().len();
+    // block height, balances root, schedules hash and reentrancy policy
+    let metadata_len = 8 + 2 * ident_len + 1;
+
+    let (code_hash, state) = proof.contract().unwrap();
+    let state_len = Canon::<MS>::encoded_len(state);
+    // the metadata and the path, followed by the terminal
+    let prefix = &bytes[..len - 1 - ident_len - state_len];
+
+    let tampered = |offset: usize| {
+        let mut bytes = bytes.clone();
+        bytes[offset] ^= 1;
+        bytes
+    };
+
+    // altering the block height, the first split bit, the first sibling or
+    // the state invalidates the proof
+    for offset in [0, metadata_len + 2, metadata_len + 4, len - 1].iter() {
+        if let Some(proof) = decode(&tampered(*offset)) {
+            assert!(!verify_proof(&root, &counter_id, &proof));
+        }
+    }
+
+    // a present contract cannot be passed off as the contract its own walk
+    // ends at instead
+    let state_hash = <MS as Store>::Ident::from_bytes(state.as_bytes());
+    let mut forged = prefix.to_vec();
+    forged.push(1);
+    forged.extend_from_slice(counter_id.as_bytes());
+    forged.extend_from_slice(code_hash.as_ref());
+    forged.extend_from_slice(state_hash.as_ref());
+
+    let forged = decode(&forged).expect("the forged proof decodes");
+    assert!(forged.contract().is_none());
+    assert!(!verify_proof(&root, &counter_id, &forged));
+
+    // nor can the contracts trie be passed off as empty
+    let mut forged = bytes[..metadata_len].to_vec();
+    forged.extend_from_slice(&[0, 0, 2]);
+
+    let forged = decode(&forged).expect("the forged proof decodes");
+    assert!(!verify_proof(&root, &counter_id, &forged));
+
+    // unknown terminals and paths longer than a key are rejected
+    let mut forged = prefix.to_vec();
+    forged.push(3);
+    assert!(decode(&forged).is_none());
+
+    let mut forged = bytes[..metadata_len].to_vec();
+    forged.extend_from_slice(&[0xff, 0xff]);
+    assert!(decode(&forged).is_none());
+}
+
+#[test]
+fn block_lifecycle() {
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::with_store(store.clone());
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+    let contract =
+        Contract::new(Counter::new(99), code.to_vec(), &store).unwrap();
+    let contract_id = network.deploy(contract).unwrap();
+
+    let payer = ContractId::reserved(42);
+    network.deposit(payer, 1_000_000_000).unwrap();
+
+    let increment = |gas_limit| {
+        BlockTransaction::new(
+            payer,
+            contract_id,
+            &counter::INCREMENT,
+            Fee::new(gas_limit, 1),
+            &store,
+        )
+        .unwrap()
+    };
+
+    let read = |network: &NetworkState<MS>| {
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        network
+            .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+            .unwrap()
+    };
+
+    // transactions are applied in order, failing ones being part of the
+    // block
+
+    let mut block = network.begin_block(1).unwrap();
+
+    assert!(block.apply(increment(1_000_000)).unwrap().result().is_ok());
+    assert!(matches!(
+        block.apply(increment(10)).unwrap().result(),
+        Err(VMError::OutOfGas)
+    ));
+    assert!(block.apply(increment(1_000_000)).unwrap().result().is_ok());
+
+    // transactions whose fee cannot be covered are rejected
+
+    assert!(matches!(
+        block.apply(increment(u64::MAX)),
+        Err(VMError::NotEnoughFunds)
+    ));
+
+    assert_eq!(read(block.state()), 101);
+
+    let finalized = block.finalize().unwrap();
+
+    assert_eq!(finalized.block_height(), 1);
+    assert_eq!(finalized.receipts().len(), 3);
+    assert_eq!(*finalized.root(), network.root().unwrap());
+
+    let spent: u64 = finalized
+        .receipts()
+        .iter()
+        .map(|receipt| receipt.fee())
+        .sum();
+    assert_eq!(network.balance(&payer).unwrap(), 1_000_000_000 - spent);
+
+    assert_eq!(network.block_height(), 1);
+    assert_eq!(read(&network), 101);
+
+    // discarded blocks leave the state untouched
+
+    let root = network.root().unwrap();
+
+    let mut block = network.begin_block(2).unwrap();
+    block.apply(increment(1_000_000)).unwrap();
+    block.discard();
+
+    assert_eq!(network.root().unwrap(), root);
+    assert_eq!(network.block_height(), 1);
+    assert_eq!(read(&network), 101);
+
+    // blocks must be above the height of the state
+
+    assert!(matches!(
+        network.begin_block(1),
+        Err(VMError::InvalidBlockHeight(1))
+    ));
+}
+
+#[test]
+fn checkpoints() {
+    let store = MS::new();
+
+    let mut network = NetworkState::<MS>::with_store(store.clone());
+
+    let code = include_bytes!("contracts/counter/counter.wasm");
+    let contract =
+        Contract::new(Counter::new(99), code.to_vec(), &store).unwrap();
+    let contract_id = network.deploy(contract).unwrap();
+
+    let mut gas = GasMeter::with_limit(1_000_000_000);
+
+    let mut increment = |network: &mut NetworkState<MS>| {
+        network
+            .transact::<_, ()>(contract_id, counter::INCREMENT, &mut gas)
+            .unwrap()
+    };
+
+    let read = |network: &NetworkState<MS>| {
+        let mut gas = GasMeter::with_limit(1_000_000_000);
+        network
+            .query::<_, i32>(contract_id, counter::READ_VALUE, &mut gas)
+            .unwrap()
+    };
+
+    let first = network.checkpoint();
+    let first_root = network.root().unwrap();
+
+    increment(&mut network);
+
+    let second = network.checkpoint();
+    let second_root = network.root().unwrap();
+
+    increment(&mut network);
+    network.deposit(contract_id, 1_000).unwrap();
+    assert_eq!(read(&network), 101);
+
+    // checkpoints can be reverted to more than once
+
+    for _ in 0..2 {
+        network.revert_to(&second).unwrap();
+        assert_eq!(read(&network), 100);
+        assert_eq!(network.balance(&contract_id).unwrap(), 0);
+        assert_eq!(network.root().unwrap(), second_root);
+
+        increment(&mut network);
+    }
+
+    // reverting to a checkpoint releases the ones taken after it
+
+    network.revert_to(&first).unwrap();
+    assert_eq!(read(&network), 99);
+    assert_eq!(network.root().unwrap(), first_root);
+
+    assert!(matches!(
+        network.revert_to(&second),
+        Err(VMError::UnknownCheckpoint)
+    ));
+    assert!(matches!(
+        network.release(second),
+        Err(VMError::UnknownCheckpoint)
+    ));
+
+    // released checkpoints leave the state as it is
+
+    increment(&mut network);
+    network.release(first).unwrap();
+    assert_eq!(read(&network), 100);
+
+    // clones of the state have their own copy of the checkpoints
+
+    let third = network.checkpoint();
+    increment(&mut network);
+    let fourth = network.checkpoint();
+
+    let mut clone = network.clone();
+    clone.revert_to(&third).unwrap();
+    assert_eq!(read(&clone), 100);
+    assert!(matches!(
+        clone.revert_to(&fourth),
+        Err(VMError::UnknownCheckpoint)
+    ));
+
+    increment(&mut network);
+    network.revert_to(&fourth).unwrap();
+    assert_eq!(read(&network), 101);
 }